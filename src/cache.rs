@@ -0,0 +1,369 @@
+//! Incremental builds.
+//!
+//! A build given a cache file records every file it writes along with a hash
+//! of everything the file was produced from: the templates and partials it was
+//! rendered with, the helper scripts, and the values of the context variables those templates
+//! refer to, which take in the Markdown and configuration behind them. The
+//! next build only renders and writes the files whose hash changed, and
+//! removes the files which are no longer produced.
+
+use std::{collections::{BTreeMap, BTreeSet}, path::{Path, PathBuf}};
+use color_eyre::eyre::Result;
+use handlebars::{
+    template::{Parameter, Template, TemplateElement},
+    Handlebars,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::Context;
+
+/// Version of tinytemple, which is part of every hash so that upgrades
+/// rebuild everything.
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// What a build left in the output directory.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Cache {
+    /// Version of tinytemple which wrote the cache.
+    pub version: String,
+
+    /// Output directory the cache describes.
+    pub outdir: PathBuf,
+
+    /// Every file written to the output directory, by its path relative to it.
+    pub outputs: BTreeMap<String, Output>,
+}
+
+/// A file written to the output directory.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Output {
+    /// Hash of every input of the file. Empty for files which are rewritten
+    /// by every build.
+    pub hash: String,
+
+    /// Templates and partials the file was rendered with.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub templates: Vec<String>,
+
+    /// The Markdown file the page was rendered from, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+
+    /// Context variables the templates refer to, or `*` for all of them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variables: Vec<String>,
+}
+
+impl Cache {
+    /// An empty cache for builds into `outdir`.
+    pub fn new(outdir: &Path) -> Self {
+        Self {
+            version: VERSION.to_owned(),
+            outdir: outdir.to_owned(),
+            outputs: BTreeMap::new(),
+        }
+    }
+
+    /// Read the cache at `path`, or `None` if there is none or it was written
+    /// by another version of tinytemple or for another output directory.
+    pub fn load(path: &Path, outdir: &Path) -> Option<Self> {
+        let raw = std::fs::read_to_string(path).ok()?;
+        let cache: Self = toml::from_str(&raw).ok()?;
+        (cache.version == VERSION && cache.outdir == outdir).then_some(cache)
+    }
+
+    /// Write the cache to `path`.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Whether the output `name` was written with `hash` and is still there.
+    pub fn is_current(&self, name: &str, hash: &str) -> bool {
+        !hash.is_empty()
+            && self.outputs.get(name).is_some_and(|output| output.hash == hash)
+            && self.outdir.join(name).is_file()
+    }
+}
+
+/// Everything a template's output depends on besides the page itself.
+#[derive(Debug, Clone, Default)]
+pub struct Dependencies {
+    /// The template and every partial it uses, with the hashes of their
+    /// sources. Partials which do not exist have an empty hash.
+    pub templates: BTreeMap<String, String>,
+
+    /// The top-level context variables the templates refer to, or `None` if
+    /// they may use any of them.
+    pub variables: Option<BTreeSet<String>>,
+
+    /// Hash of the helper scripts, which any template may call.
+    pub helpers: String,
+}
+
+impl Dependencies {
+    /// Find the dependencies of the template `name` registered in `engine`.
+    /// `sources` holds the hashes of every template's source.
+    pub fn of(engine: &Handlebars, name: &str, sources: &BTreeMap<String, String>) -> Self {
+        let mut deps = Self { variables: Some(BTreeSet::new()), ..Self::default() };
+        let mut dynamic = false;
+        let mut pending = vec![name.to_owned()];
+        while let Some(name) = pending.pop() {
+            if deps.templates.contains_key(&name) {
+                continue;
+            }
+            deps.templates.insert(name.clone(), sources.get(&name).cloned().unwrap_or_default());
+            if let Some(template) = engine.get_template(&name) {
+                deps.walk(template, false, &mut pending, &mut dynamic);
+            }
+        }
+
+        // A partial chosen at render time could be any template.
+        if dynamic {
+            deps.templates.extend(sources.iter().map(|(name, hash)| (name.clone(), hash.clone())));
+        }
+        deps
+    }
+
+    /// Hash the inputs of the output `name` rendered with `ctx`.
+    pub fn hash(&self, name: &str, ctx: &Context) -> String {
+        let mut parts = vec![name.to_owned(), self.helpers.clone()];
+        for (template, source) in &self.templates {
+            parts.push(template.clone());
+            parts.push(source.clone());
+        }
+        let used: Context = match &self.variables {
+            Some(variables) => ctx.iter()
+                .filter(|(key, _)| variables.contains(*key))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            None => ctx.clone(),
+        };
+        parts.push(toml::to_string(&used).unwrap_or_else(|_| format!("{used:?}")));
+        hash(&parts)
+    }
+
+    /// Record the dependencies of an output in the cache.
+    pub fn output(&self, hash: String, content: Option<String>) -> Output {
+        Output {
+            hash,
+            templates: self.templates.keys().cloned().collect(),
+            content,
+            variables: match &self.variables {
+                Some(variables) => variables.iter().cloned().collect(),
+                None => vec!["*".to_owned()],
+            },
+        }
+    }
+
+    /// Walk `template`, noting the variables and partials it uses. Within
+    /// `scoped` blocks like `{{#each}}`, `this` is something other than the
+    /// whole context.
+    fn walk(&mut self, template: &Template, scoped: bool, partials: &mut Vec<String>, dynamic: &mut bool) {
+        for element in &template.elements {
+            self.element(element, scoped, partials, dynamic);
+        }
+    }
+
+    fn element(&mut self, element: &TemplateElement, scoped: bool, partials: &mut Vec<String>, dynamic: &mut bool) {
+        match element {
+            TemplateElement::RawString(_) | TemplateElement::Comment(_) => (),
+            TemplateElement::HtmlExpression(helper)
+            | TemplateElement::Expression(helper)
+            | TemplateElement::HelperBlock(helper) => {
+                // Without parameters, `{{#title}}` may name a variable rather
+                // than a helper.
+                if helper.params.is_empty() && helper.hash.is_empty() {
+                    self.parameter(&helper.name, scoped, partials, dynamic);
+                }
+                for param in helper.params.iter().chain(helper.hash.values()) {
+                    self.parameter(param, scoped, partials, dynamic);
+                }
+                let rescoped = matches!(helper.name.as_name(), Some("each" | "with"));
+                if let Some(inner) = &helper.template {
+                    self.walk(inner, scoped || rescoped, partials, dynamic);
+                }
+                if let Some(inverse) = &helper.inverse {
+                    self.walk(inverse, scoped, partials, dynamic);
+                }
+            },
+            TemplateElement::DecoratorExpression(decorator)
+            | TemplateElement::DecoratorBlock(decorator)
+            | TemplateElement::PartialExpression(decorator)
+            | TemplateElement::PartialBlock(decorator) => {
+                let partial = matches!(
+                    element,
+                    TemplateElement::PartialExpression(_) | TemplateElement::PartialBlock(_)
+                );
+                if partial {
+                    match &decorator.name {
+                        // Names are looked up exactly as they are written,
+                        // brackets and quotes included.
+                        Parameter::Name(name) => partials.push(name.clone()),
+                        Parameter::Path(handlebars::Path::Relative((_, raw))) => partials.push(raw.clone()),
+                        Parameter::Literal(handlebars::JsonValue::String(name)) => partials.push(name.clone()),
+                        // `@partial-block` renders the caller's own block.
+                        Parameter::Path(handlebars::Path::Local(_)) => (),
+                        _ => *dynamic = true,
+                    }
+                }
+                for param in decorator.params.iter().chain(decorator.hash.values()) {
+                    self.parameter(param, scoped, partials, dynamic);
+                }
+                if let Some(inner) = &decorator.template {
+                    self.walk(inner, scoped, partials, dynamic);
+                }
+            },
+        }
+    }
+
+    fn parameter(&mut self, param: &Parameter, scoped: bool, partials: &mut Vec<String>, dynamic: &mut bool) {
+        match param {
+            Parameter::Name(name) => self.variable(name, scoped),
+            Parameter::Path(handlebars::Path::Relative((_, raw))) => self.variable(raw, scoped),
+            Parameter::Path(handlebars::Path::Local((_, name, _))) => {
+                if name == "root" {
+                    self.variables = None;
+                }
+            },
+            Parameter::Literal(_) => (),
+            Parameter::Subexpression(subexpression) => {
+                self.element(subexpression.as_element(), scoped, partials, dynamic);
+            },
+        }
+    }
+
+    /// Note the top-level variable a path like `../posts.[0].title` starts at.
+    /// Paths to the whole context make every variable a dependency.
+    fn variable(&mut self, path: &str, scoped: bool) {
+        let mut path = path;
+        let mut scoped = scoped;
+        loop {
+            if path.starts_with("../") || path.starts_with("@root") {
+                scoped = false;
+            }
+            let stripped = ["this.", "this/", "./", "@root.", "@root/", "../"]
+                .iter()
+                .find_map(|prefix| path.strip_prefix(prefix));
+            match stripped {
+                Some(rest) => path = rest,
+                None => break,
+            }
+        }
+
+        let root = match path.strip_prefix('[') {
+            Some(key) => key.split(']').next().unwrap_or_default(),
+            None => path.split(['.', '/']).next().unwrap_or_default(),
+        };
+        match root {
+            "" | "this" | "." | "@root" if scoped => (),
+            "" | "this" | "." | "@root" => self.variables = None,
+            root => {
+                if let Some(variables) = &mut self.variables {
+                    variables.insert(root.to_owned());
+                }
+            }
+        }
+    }
+}
+
+/// Hash `parts` into a hex string.
+pub fn hash<S: AsRef<[u8]>>(parts: &[S]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part.as_ref());
+        hasher.update([0]);
+    }
+    hasher.update(VERSION);
+    hasher.finalize().iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The dependencies of the first of `templates`, registered by name.
+    fn deps(templates: &[(&str, &str)]) -> Dependencies {
+        let mut engine = Handlebars::new();
+        for (name, source) in templates {
+            engine.register_template_string(name, source).unwrap();
+        }
+        let sources = templates.iter().map(|(name, source)| (name.to_string(), hash(&[source]))).collect();
+        Dependencies::of(&engine, templates[0].0, &sources)
+    }
+
+    fn variables(template: &str) -> Option<Vec<String>> {
+        deps(&[("page", template)]).variables.map(|variables| variables.into_iter().collect())
+    }
+
+    #[test]
+    fn finds_top_level_variables() {
+        assert_eq!(
+            variables("{{title}} {{site.name}} {{#if draft}}{{date author.name}}{{/if}} {{upper (lower tag)}}"),
+            Some(vec!["author", "draft", "site", "tag", "title"].into_iter().map(String::from).collect()),
+        );
+    }
+
+    #[test]
+    fn strips_path_prefixes() {
+        assert_eq!(
+            variables("{{this.title}} {{./a}} {{this/b}} {{@root.c.d}} {{@root/e}} {{[f g].h}}"),
+            Some(vec!["a", "b", "c", "e", "f g", "title"].into_iter().map(String::from).collect()),
+        );
+    }
+
+    #[test]
+    fn whole_context_depends_on_everything() {
+        assert_eq!(variables("{{this}}"), None);
+        assert_eq!(variables("{{json @root}}"), None);
+        assert_eq!(variables("{{#each posts}}{{../this}}{{/each}}"), None);
+        assert_eq!(variables("{{#each posts}}{{json @root}}{{/each}}"), None);
+    }
+
+    #[test]
+    fn blocks_rescope_this() {
+        assert_eq!(
+            variables("{{#each posts}}{{this}} {{@index}} {{../site}}{{/each}}{{#with author}}{{this}}{{/with}}"),
+            Some(vec!["author", "posts", "site"].into_iter().map(String::from).collect()),
+        );
+        // Only the block itself is rescoped, not its `{{else}}`.
+        assert_eq!(variables("{{#each posts}}{{this}}{{else}}{{this}}{{/each}}"), None);
+        assert_eq!(variables("{{#if posts}}{{this}}{{/if}}"), None);
+    }
+
+    #[test]
+    fn follows_partials() {
+        let deps = deps(&[
+            ("page", "{{> header}}{{> [my partial]}}{{> missing}}{{#> layout}}{{body}}{{/layout}}"),
+            ("header", "{{> nav/menu}}{{site}}"),
+            ("nav/menu", "{{menu}}"),
+            ("[my partial]", "{{x}}"),
+            ("layout", "{{> @partial-block}}{{title}}"),
+            ("unused", "{{y}}"),
+        ]);
+        let templates: Vec<&str> = deps.templates.keys().map(String::as_str).collect();
+        assert_eq!(templates, ["[my partial]", "header", "layout", "missing", "nav/menu", "page"]);
+        assert_eq!(deps.templates["missing"], "");
+        assert_eq!(deps.templates["header"], hash(&["{{> nav/menu}}{{site}}"]));
+        let variables: Vec<&str> = deps.variables.iter().flatten().map(String::as_str).collect();
+        assert_eq!(variables, ["body", "menu", "site", "title", "x"]);
+    }
+
+    #[test]
+    fn dynamic_partials_depend_on_every_template() {
+        let deps = deps(&[("page", "{{> (lookup partials 'name')}}"), ("a", ""), ("b", "")]);
+        let templates: Vec<&str> = deps.templates.keys().map(String::as_str).collect();
+        assert_eq!(templates, ["a", "b", "page"]);
+    }
+
+    #[test]
+    fn hashes_only_used_variables() {
+        let deps = deps(&[("page", "{{title}}")]);
+        let ctx = |title: &str, other: i64| -> Context {
+            toml::from_str(&format!("title = '{title}'\nother = {other}")).unwrap()
+        };
+        assert_eq!(deps.hash("page", &ctx("a", 1)), deps.hash("page", &ctx("a", 2)));
+        assert_ne!(deps.hash("page", &ctx("a", 1)), deps.hash("page", &ctx("b", 1)));
+        assert_ne!(deps.hash("page", &ctx("a", 1)), deps.hash("other", &ctx("a", 1)));
+    }
+}
@@ -0,0 +1,103 @@
+//! Collections of pages.
+//!
+//! Every top-level directory of the source tree holding Markdown files is a
+//! collection, so `posts/hello.md` belongs to the `posts` collection. Templates
+//! can list a collection's pages through `collections.<name>`, newest first.
+
+use std::{cmp::{Ordering, Reverse}, collections::BTreeMap};
+use chrono::{DateTime, FixedOffset};
+use crate::{content::Page, Context};
+
+/// Pages grouped by collection name.
+pub type Collections<'a> = BTreeMap<String, Vec<&'a Page>>;
+
+/// Group `pages` into their collections and sort each one.
+pub fn collect(pages: &[Page]) -> Collections<'_> {
+    let mut collections = Collections::new();
+    for page in pages {
+        if let Some(name) = page.collection() {
+            collections.entry(name.to_owned()).or_default().push(page);
+        }
+    }
+
+    for pages in collections.values_mut() {
+        pages.sort_by(|a, b| compare(a, b));
+    }
+    collections
+}
+
+/// Order pages newest first. Dates are compared as timestamps, so that
+/// differing formats and offsets sort correctly. Dates which cannot be parsed
+/// come after those which can, newest first by their text, and undated pages
+/// come last. Ties are broken by name so builds are deterministic.
+pub fn compare(a: &Page, b: &Page) -> Ordering {
+    sort_key(a).cmp(&sort_key(b)).then_with(|| a.name.cmp(&b.name))
+}
+
+/// The key `compare` orders pages by: how well the date is known, then the
+/// timestamp and the text of the date, both reversed to put newer ones first.
+type SortKey<'a> = (u8, Reverse<Option<DateTime<FixedOffset>>>, Reverse<Option<&'a str>>);
+
+fn sort_key(page: &Page) -> SortKey<'_> {
+    let datetime = page.datetime();
+    let rank = match (page.date(), datetime) {
+        (Some(_), Some(_)) => 0,
+        (Some(_), None) => 1,
+        (None, _) => 2,
+    };
+    (rank, Reverse(datetime), Reverse(page.date()))
+}
+
+/// The variables describing a page in a listing: its front matter along with
+/// its `name`, `url`, `permalink` and `summary`.
+pub fn entry(page: &Page, ctx: &Context) -> toml::Value {
+    let mut entry = page.front_matter.clone();
+    let url = page.url();
+    entry.insert("name".to_owned(), toml::Value::String(page.name.clone()));
+    if let Some(permalink) = crate::permalink(ctx, &url) {
+        entry.insert("permalink".to_owned(), toml::Value::String(permalink));
+    }
+    entry.insert("url".to_owned(), toml::Value::String(url));
+    entry.insert("summary".to_owned(), toml::Value::String(page.summary.clone()));
+    toml::Value::Table(entry)
+}
+
+/// Build the `collections` table exposed to templates.
+pub fn to_context(collections: &Collections, ctx: &Context) -> Context {
+    collections.iter()
+        .map(|(name, pages)| {
+            let entries = pages.iter().map(|page| entry(page, ctx)).collect();
+            (name.clone(), toml::Value::Array(entries))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(name: &str, date: Option<&str>) -> Page {
+        let mut front_matter = Context::new();
+        if let Some(date) = date {
+            front_matter.insert("date".to_owned(), toml::Value::String(date.to_owned()));
+        }
+        Page { name: name.to_owned(), front_matter, ..Default::default() }
+    }
+
+    #[test]
+    fn pages_sort_newest_first() {
+        let pages = [
+            page("undated", None),
+            page("bad", Some("someday")),
+            page("late", Some("2024-05-31T23:00:00-10:00")),
+            page("june", Some("2024-06-01")),
+            page("worse", Some("whenever")),
+            page("may", Some("2024-05-31")),
+            page("also-undated", None),
+        ];
+        let mut sorted: Vec<&Page> = pages.iter().collect();
+        sorted.sort_by(|a, b| compare(a, b));
+        let names: Vec<&str> = sorted.iter().map(|page| page.name.as_str()).collect();
+        assert_eq!(names, ["late", "june", "may", "worse", "bad", "also-undated", "undated"]);
+    }
+}
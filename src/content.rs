@@ -0,0 +1,544 @@
+//! Markdown content files and their front matter.
+//!
+//! A content file may start with a block of TOML delimited by `+++` lines, or
+//! a block of YAML delimited by `---` lines. The block is parsed into a
+//! [`Context`] which is merged over the global configuration for that page.
+
+use std::{collections::HashSet, path::{Path, PathBuf}};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime};
+use color_eyre::eyre::{Result, bail, eyre};
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Tag};
+use tracing::{event, Level};
+use crate::{escape, highlight::Highlighter, shortcodes::{self, Shortcodes}, Context};
+
+/// Marker separating the summary of a page from the rest of its content.
+pub const SUMMARY_MARKER: &str = "<!-- more -->";
+
+/// A parsed Markdown content file.
+#[derive(Debug, Clone, Default)]
+pub struct Page {
+    /// Path of the file relative to the source directory, without the `.md`
+    /// extension and with `/` separators.
+    pub name: String,
+
+    /// The file the page was read from.
+    pub path: PathBuf,
+
+    /// Variables declared in the front matter, if there was any.
+    pub front_matter: Context,
+
+    /// The Markdown body, rendered to HTML.
+    pub content: String,
+
+    /// A short HTML excerpt of the content for use in listings.
+    pub summary: String,
+
+    /// The headings of the content, nested by level.
+    pub toc: Vec<Heading>,
+}
+
+impl Page {
+    /// Parse the raw text of a content file.
+    pub fn parse(raw: &str, markdown: &Markdown) -> Result<Self> {
+        let (front_matter, body) = split_front_matter(raw)?;
+        let markdown = Markdown { page: Some(&front_matter), ..*markdown };
+        let (content, toc) = markdown.render_with_toc(body)?;
+
+        // Prefer an explicit summary, then everything above the marker, and
+        // finally just the first paragraph.
+        let summary = match front_matter.get("summary") {
+            Some(toml::Value::String(summary)) => summary.clone(),
+            _ => match body.split_once(SUMMARY_MARKER) {
+                Some((above, _)) => markdown.render(above)?,
+                None => match content.find("</p>") {
+                    Some(end) => content[..end + "</p>".len()].to_owned(),
+                    None => content.clone(),
+                },
+            },
+        };
+
+        Ok(Self {
+            front_matter,
+            content,
+            summary,
+            toc,
+            ..Self::default()
+        })
+    }
+
+    /// Read and parse the content file at `path`.
+    pub fn load(name: &str, path: &Path, markdown: &Markdown) -> Result<Self> {
+        let raw = std::fs::read_to_string(path)?;
+        Ok(Self {
+            name: name.to_owned(),
+            path: path.to_owned(),
+            ..Self::parse(&raw, markdown)?
+        })
+    }
+
+    /// Site-relative URL the page is rendered to.
+    pub fn url(&self) -> String {
+        crate::url_for(&self.name)
+    }
+
+    /// The `date` front matter value, if any.
+    pub fn date(&self) -> Option<&str> {
+        self.front_matter.get("date").and_then(|d| d.as_str())
+    }
+
+    /// The `date` front matter value as a timestamp, if it can be parsed.
+    pub fn datetime(&self) -> Option<DateTime<FixedOffset>> {
+        self.date().and_then(parse_date)
+    }
+
+    /// The collection a page belongs to: the top-level directory it is in.
+    pub fn collection(&self) -> Option<&str> {
+        self.name.split_once('/').map(|(dir, _)| dir)
+    }
+}
+
+/// Which pages a build publishes. Drafts and pages dated in the future are
+/// only published when asked for, and pages past their `expiry_date` never.
+#[derive(Debug, Clone, Copy)]
+pub struct Publishing {
+    /// Whether pages with `draft = true` are published.
+    pub drafts: bool,
+
+    /// Whether pages with a `date` after `now` are published.
+    pub future: bool,
+
+    /// The time of the build.
+    pub now: DateTime<FixedOffset>,
+}
+
+impl Publishing {
+    /// Whether a page or template with `front_matter` is published.
+    pub fn includes(&self, front_matter: &Context) -> bool {
+        let date = |key: &str| front_matter.get(key).and_then(|d| d.as_str()).and_then(parse_date);
+        let draft = matches!(front_matter.get("draft"), Some(toml::Value::Boolean(true)));
+        let future = date("date").is_some_and(|date| date > self.now);
+        let expired = date("expiry_date").is_some_and(|expiry| expiry <= self.now);
+        (self.drafts || !draft) && (self.future || !future) && !expired
+    }
+}
+
+/// Settings for rendering Markdown to HTML.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Markdown<'a> {
+    /// Highlighter for fenced code blocks, if highlighting is enabled.
+    pub highlighter: Option<&'a Highlighter>,
+
+    /// Whether headings get a link to themselves.
+    pub anchors: bool,
+
+    /// Renderer of the shortcodes in the document, if they are expanded.
+    pub shortcodes: Option<Shortcodes<'a>>,
+
+    /// Front matter of the page being rendered, for its shortcodes.
+    pub page: Option<&'a Context>,
+}
+
+impl Markdown<'_> {
+    /// Render a Markdown document to an HTML string.
+    pub fn render(&self, body: &str) -> Result<String> {
+        Ok(self.render_with_toc(body)?.0)
+    }
+
+    /// Render a Markdown document to an HTML string, along with the table of
+    /// contents of its headings. Fails if one of its shortcodes does.
+    pub fn render_with_toc(&self, body: &str) -> Result<(String, Vec<Heading>)> {
+        let shortcodes = match self.shortcodes {
+            Some(shortcodes) => shortcodes,
+            None => return Ok(self.convert(body)),
+        };
+        let empty = Context::new();
+        let (body, rendered) = shortcodes.expand(body, self, self.page.unwrap_or(&empty))?;
+        let (html, toc) = self.convert(&body);
+        Ok((shortcodes::substitute(html, &rendered), toc))
+    }
+
+    /// Convert Markdown without shortcodes to HTML and a table of contents.
+    fn convert(&self, body: &str) -> (String, Vec<Heading>) {
+        let parse_opts = pulldown_cmark::Options::all();
+        let parser = pulldown_cmark::Parser::new_ext(body, parse_opts);
+
+        let events = match self.highlighter {
+            Some(highlighter) => highlight_code(parser, highlighter),
+            None => parser.collect(),
+        };
+        let (events, headings) = self.identify_headings(events);
+
+        let mut html_output = String::new();
+        pulldown_cmark::html::push_html(&mut html_output, events.into_iter());
+
+        let mut toc = Vec::new();
+        for heading in headings {
+            heading.nest_into(&mut toc);
+        }
+        (html_output, toc)
+    }
+
+    /// Give every heading an `id`, either its `{#custom-id}` or a slug of its
+    /// text made unique within the document, and a self-link if enabled.
+    /// Custom ids are kept exactly as written, so slugs steer clear of them.
+    /// Returns the headings in document order.
+    fn identify_headings<'a>(&self, events: Vec<Event<'a>>) -> (Vec<Event<'a>>, Vec<Heading>) {
+        let mut output = Vec::with_capacity(events.len());
+        let mut headings = Vec::new();
+        let mut ids = HashSet::new();
+        let custom: HashSet<&str> = events.iter()
+            .filter_map(|event| match event {
+                Event::Start(Tag::Heading(_, Some(id), _)) => Some(*id),
+                _ => None,
+            })
+            .collect();
+        let mut current: Option<OpenHeading> = None;
+
+        for event in events {
+            match (&mut current, event) {
+                (None, Event::Start(Tag::Heading(level, id, classes))) => {
+                    current = Some(OpenHeading { level, id, classes, inner: Vec::new() });
+                },
+                (Some(OpenHeading { level, id, classes, inner }), Event::End(Tag::Heading(..))) => {
+                    let text: String = inner.iter()
+                        .filter_map(|event| match event {
+                            Event::Text(text) | Event::Code(text) => Some(text.as_ref()),
+                            _ => None,
+                        })
+                        .collect();
+                    // Shortcodes are only put in place once the whole page is
+                    // rendered, so they are left out of the id and the title.
+                    let text = shortcodes::strip_placeholders(&text);
+                    let text = text.trim();
+
+                    let unique = match id {
+                        Some(id) => {
+                            if !ids.insert(id.to_string()) {
+                                event!(Level::WARN, id = %id, "Heading id is used more than once.");
+                            }
+                            id.to_string()
+                        },
+                        None => {
+                            let base = match crate::slugify(text) {
+                                slug if slug.is_empty() => "section".to_owned(),
+                                slug => slug,
+                            };
+                            let mut unique = base.clone();
+                            let mut n = 1;
+                            while custom.contains(unique.as_str()) || !ids.insert(unique.clone()) {
+                                unique = format!("{base}-{n}");
+                                n += 1;
+                            }
+                            unique
+                        },
+                    };
+
+                    let class = if classes.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" class=\"{}\"", escape(&classes.join(" ")))
+                    };
+                    output.push(Event::Html(format!("<{level} id=\"{}\"{class}>", escape(&unique)).into()));
+                    output.append(inner);
+                    if self.anchors {
+                        let anchor = format!(" <a class=\"anchor\" href=\"#{}\" aria-hidden=\"true\">#</a>", escape(&unique));
+                        output.push(Event::Html(anchor.into()));
+                    }
+                    output.push(Event::Html(format!("</{level}>\n").into()));
+
+                    headings.push(Heading {
+                        level: *level as u32,
+                        id: unique,
+                        title: escape(text),
+                        children: Vec::new(),
+                    });
+                    current = None;
+                },
+                (Some(OpenHeading { inner, .. }), event) => inner.push(event),
+                (None, event) => output.push(event),
+            }
+        }
+
+        (output, headings)
+    }
+}
+
+/// A heading whose end has not been reached yet.
+struct OpenHeading<'a> {
+    level: HeadingLevel,
+    id: Option<&'a str>,
+    classes: Vec<&'a str>,
+    inner: Vec<Event<'a>>,
+}
+
+/// A heading of a page, with the headings under it.
+#[derive(Debug, Clone, Default)]
+pub struct Heading {
+    /// Level of the heading, from 1 for `<h1>` to 6.
+    pub level: u32,
+
+    /// The `id` the heading can be linked to with.
+    pub id: String,
+
+    /// Text of the heading, as HTML.
+    pub title: String,
+
+    /// Headings of a lower level that follow this one before the next of
+    /// the same or higher level.
+    pub children: Vec<Heading>,
+}
+
+impl Heading {
+    /// Template variables describing the heading and its children.
+    pub fn to_context(&self) -> Context {
+        let mut heading = Context::new();
+        heading.insert("level".to_owned(), toml::Value::Integer(self.level.into()));
+        heading.insert("id".to_owned(), toml::Value::String(self.id.clone()));
+        heading.insert("url".to_owned(), toml::Value::String(format!("#{}", self.id)));
+        heading.insert("title".to_owned(), toml::Value::String(self.title.clone()));
+        let children = self.children.iter().map(|child| toml::Value::Table(child.to_context())).collect();
+        heading.insert("children".to_owned(), toml::Value::Array(children));
+        heading
+    }
+
+    /// Add the heading to a table of contents, under the last heading of a
+    /// higher level if there is one.
+    fn nest_into(self, toc: &mut Vec<Heading>) {
+        match toc.last_mut() {
+            Some(last) if last.level < self.level => self.nest_into(&mut last.children),
+            _ => toc.push(self),
+        }
+    }
+}
+
+/// Replace the fenced code blocks among `events` with their highlighted HTML.
+fn highlight_code<'a>(events: impl Iterator<Item = Event<'a>>, highlighter: &Highlighter) -> Vec<Event<'a>> {
+    let mut output = Vec::new();
+    let mut code: Option<(String, String)> = None;
+    for event in events {
+        match (&mut code, event) {
+            (None, Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info)))) => {
+                code = Some((info.to_string(), String::new()));
+            },
+            (Some((_, text)), Event::Text(t)) => text.push_str(&t),
+            (Some((info, text)), Event::End(Tag::CodeBlock(_))) => {
+                output.push(Event::Html(highlighter.highlight(info, text).into()));
+                code = None;
+            },
+            (_, event) => output.push(event),
+        }
+    }
+    output
+}
+
+/// Render a Markdown document to an HTML string with the default settings.
+pub fn render_markdown(body: &str) -> String {
+    Markdown::default().convert(body).0
+}
+
+/// Parse a front matter date. Accepts RFC 3339 timestamps, as well as local
+/// datetimes and plain dates, which are taken to be in UTC.
+pub fn parse_date(text: &str) -> Option<DateTime<FixedOffset>> {
+    let text = text.trim();
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(text) {
+        return Some(datetime);
+    }
+    let utc = FixedOffset::east_opt(0)?;
+    for format in ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"] {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(text, format) {
+            return Some(datetime.and_utc().with_timezone(&utc));
+        }
+    }
+    let date = NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()?;
+    Some(date.and_hms_opt(0, 0, 0)?.and_utc().with_timezone(&utc))
+}
+
+/// Separate the front matter of a content file from its body.
+///
+/// Files without front matter produce an empty context and the whole input as
+/// the body.
+pub fn split_front_matter(raw: &str) -> Result<(Context, &str)> {
+    let raw = raw.strip_prefix('\u{feff}').unwrap_or(raw);
+
+    let (first, rest) = match raw.split_once('\n') {
+        Some((first, rest)) => (first.trim_end(), rest),
+        None => (raw.trim_end(), ""),
+    };
+
+    let delimiter = match first {
+        "+++" => "+++",
+        "---" => "---",
+        _ => return Ok((Context::new(), raw)),
+    };
+
+    // Find the closing delimiter on a line of its own.
+    let mut offset = 0;
+    let mut close = None;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == delimiter {
+            close = Some((offset, offset + line.len()));
+            break;
+        }
+        offset += line.len();
+    }
+
+    let (header, body) = match close {
+        Some((start, end)) => (&rest[..start], &rest[end..]),
+        None => bail!("Front matter opened with `{delimiter}` is never closed."),
+    };
+
+    let mut front_matter = match delimiter {
+        "+++" => toml::from_str::<Context>(header)?,
+        _ => match serde_yaml::from_str::<serde_yaml::Value>(header)? {
+            serde_yaml::Value::Null => Context::new(),
+            serde_yaml::Value::Mapping(map) => yaml_table(map)?,
+            _ => bail!("YAML front matter must be a mapping."),
+        },
+    };
+    stringify_datetimes(&mut front_matter);
+
+    Ok((front_matter, body))
+}
+
+/// Convert a YAML mapping into a TOML table. Null values are dropped, since
+/// TOML has no way to represent them.
+fn yaml_table(map: serde_yaml::Mapping) -> Result<Context> {
+    let mut table = Context::new();
+    for (key, value) in map {
+        let key = match key {
+            serde_yaml::Value::String(s) => s,
+            serde_yaml::Value::Bool(b) => b.to_string(),
+            serde_yaml::Value::Number(n) => n.to_string(),
+            other => return Err(eyre!("Unsupported YAML key: {other:?}")),
+        };
+        if let Some(value) = yaml_value(value)? {
+            table.insert(key, value);
+        }
+    }
+    Ok(table)
+}
+
+/// Convert a YAML value into a TOML one, or `None` for null.
+pub fn yaml_value(value: serde_yaml::Value) -> Result<Option<toml::Value>> {
+    Ok(Some(match value {
+        serde_yaml::Value::Null => return Ok(None),
+        serde_yaml::Value::Bool(b) => toml::Value::Boolean(b),
+        serde_yaml::Value::Number(n) => match n.as_i64() {
+            Some(i) => toml::Value::Integer(i),
+            None => toml::Value::Float(n.as_f64().unwrap_or_default()),
+        },
+        serde_yaml::Value::String(s) => toml::Value::String(s),
+        serde_yaml::Value::Sequence(seq) => {
+            let mut array = Vec::with_capacity(seq.len());
+            for item in seq {
+                if let Some(item) = yaml_value(item)? {
+                    array.push(item);
+                }
+            }
+            toml::Value::Array(array)
+        },
+        serde_yaml::Value::Mapping(map) => toml::Value::Table(yaml_table(map)?),
+        serde_yaml::Value::Tagged(tagged) => return yaml_value(tagged.value),
+    }))
+}
+
+/// TOML datetimes serialize as an opaque wrapper object, which templates
+/// cannot print. Store them as their RFC 3339 text instead.
+pub fn stringify_datetimes(table: &mut Context) {
+    for (_, value) in table.iter_mut() {
+        stringify_value(value);
+    }
+}
+
+fn stringify_value(value: &mut toml::Value) {
+    match value {
+        toml::Value::Datetime(dt) => *value = toml::Value::String(dt.to_string()),
+        toml::Value::Array(array) => array.iter_mut().for_each(stringify_value),
+        toml::Value::Table(table) => stringify_datetimes(table),
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_toml_front_matter() {
+        let (front_matter, body) = split_front_matter("+++\ntitle = \"Hello\"\ntags = [\"a\"]\n+++\nBody\n").unwrap();
+        assert_eq!(front_matter, toml::from_str("title = 'Hello'\ntags = ['a']").unwrap());
+        assert_eq!(body, "Body\n");
+    }
+
+    #[test]
+    fn split_yaml_front_matter() {
+        let raw = "---\r\ntitle: Hello\r\ndraft: true\r\nauthor: ~\r\n---\r\nBody\r\n";
+        let (front_matter, body) = split_front_matter(raw).unwrap();
+        assert_eq!(front_matter, toml::from_str("title = 'Hello'\ndraft = true").unwrap());
+        assert_eq!(body, "Body\r\n");
+    }
+
+    #[test]
+    fn split_front_matter_stringifies_dates() {
+        let (front_matter, _) = split_front_matter("+++\ndate = 2024-01-02\n+++\n").unwrap();
+        assert_eq!(front_matter["date"], toml::Value::String("2024-01-02".to_owned()));
+    }
+
+    #[test]
+    fn split_without_front_matter() {
+        let (front_matter, body) = split_front_matter("# Title\n---\n").unwrap();
+        assert!(front_matter.is_empty());
+        assert_eq!(body, "# Title\n---\n");
+
+        let (front_matter, body) = split_front_matter("\u{feff}---\n---\nBody").unwrap();
+        assert!(front_matter.is_empty());
+        assert_eq!(body, "Body");
+    }
+
+    #[test]
+    fn split_front_matter_errors() {
+        assert!(split_front_matter("+++\ntitle = 'Hello'\n").is_err());
+        assert!(split_front_matter("+++\ntitle = \n+++\n").is_err());
+        assert!(split_front_matter("---\n- a list\n---\n").is_err());
+    }
+
+    #[test]
+    fn shortcodes_in_headings_are_left_out_of_ids() {
+        let mut engine = handlebars::Handlebars::new();
+        engine.register_template_string("_shortcodes/version", "<b>1.2</b>").unwrap();
+        let ctx = Context::new();
+        let markdown = Markdown { shortcodes: Some(Shortcodes::new(&engine, &ctx)), ..Default::default() };
+        let (html, toc) = markdown.render_with_toc("## Intro {{< version >}}\n").unwrap();
+        assert_eq!(html, "<h2 id=\"intro\">Intro <b>1.2</b></h2>\n");
+        assert_eq!(toc[0].id, "intro");
+        assert_eq!(toc[0].title, "Intro");
+    }
+
+    fn toc(markdown: &str) -> Vec<Heading> {
+        Markdown::default().render_with_toc(markdown).unwrap().1
+    }
+
+    #[test]
+    fn heading_ids_are_unique() {
+        let toc = toc("# A\n# A\n# A-1\n# !!!\n# ???\n");
+        let ids: Vec<&str> = toc.iter().map(|heading| heading.id.as_str()).collect();
+        assert_eq!(ids, ["a", "a-1", "a-1-1", "section", "section-1"]);
+    }
+
+    #[test]
+    fn custom_heading_ids_are_kept() {
+        let toc = toc("# Setup\n# Install {#setup}\n# Setup\n");
+        let ids: Vec<&str> = toc.iter().map(|heading| heading.id.as_str()).collect();
+        assert_eq!(ids, ["setup-1", "setup", "setup-2"]);
+    }
+
+    #[test]
+    fn headings_nest_under_higher_levels() {
+        let toc = toc("## A\n### B\n#### C\n### D\n# E\n### F\n## G\n");
+        let outline: Vec<(&str, Vec<&str>)> = toc.iter()
+            .map(|heading| (heading.title.as_str(), heading.children.iter().map(|child| child.title.as_str()).collect()))
+            .collect();
+        assert_eq!(outline, [("A", vec!["B", "D"]), ("E", vec!["F", "G"])]);
+        assert_eq!(toc[0].children[0].children[0].title, "C");
+    }
+}
@@ -0,0 +1,187 @@
+//! Data files exposed to templates.
+//!
+//! Every JSON, YAML, TOML and CSV file in the data directory is parsed by its
+//! extension and exposed to templates under `data`, nested by subdirectory:
+//! `data/team/members.yaml` is `data.team.members`. The rows of a CSV file
+//! become an array of tables keyed by its header row.
+
+use std::path::Path;
+use color_eyre::eyre::{Result, bail, eyre};
+use tracing::{event, Level};
+use crate::{content, Context};
+
+/// Read every data file in `dir` into a table, nested by subdirectory. A
+/// missing directory has no data.
+pub fn load(dir: &Path) -> Result<Context> {
+    let mut data = Context::new();
+    for entry in walkdir::WalkDir::new(dir).min_depth(1).sort_by_file_name() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) if e.io_error().is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy();
+        if !entry.file_type().is_file() || file_name.starts_with('.') || file_name.starts_with('#') {
+            continue;
+        }
+        let (stem, extension) = match file_name.rsplit_once('.') {
+            Some((stem, extension)) => (stem, extension.to_lowercase()),
+            None => (file_name.as_ref(), String::new()),
+        };
+        if !matches!(extension.as_str(), "json" | "yaml" | "yml" | "toml" | "csv") {
+            let infile = path.as_os_str().to_string_lossy();
+            event!(Level::WARN, path = %infile, "Ignoring data file of unknown type.");
+            continue;
+        }
+
+        let raw = std::fs::read_to_string(path)
+            .map_err(|e| eyre!("Unable to read {}: {e}", path.display()))?;
+        let value = parse(&raw, &extension)
+            .map_err(|e| eyre!("Unable to parse {}: {e}", path.display()))?;
+
+        // Find the table of the file's directory, creating it as needed.
+        let relative = path.strip_prefix(dir)?;
+        let mut table = &mut data;
+        for component in relative.parent().into_iter().flat_map(Path::components) {
+            let key = component.as_os_str().to_string_lossy().into_owned();
+            let inner = table.entry(key).or_insert_with(|| toml::Value::Table(Context::new()));
+            table = match inner {
+                toml::Value::Table(inner) => inner,
+                _ => bail!("{} is in a directory with the same name as another data file.", path.display()),
+            };
+        }
+        match (table.get_mut(stem), value) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(value)) => crate::merge(existing, value),
+            (Some(_), _) => bail!("{} has the same name as another data file or directory.", path.display()),
+            (None, value) => {
+                table.insert(stem.to_owned(), value);
+            },
+        }
+    }
+    content::stringify_datetimes(&mut data);
+    Ok(data)
+}
+
+/// Parse the contents of a data file with the given extension.
+fn parse(raw: &str, extension: &str) -> Result<toml::Value> {
+    Ok(match extension {
+        "json" => json_value(serde_json::from_str(raw)?).unwrap_or_else(|| toml::Value::Table(Context::new())),
+        "yaml" | "yml" => content::yaml_value(serde_yaml::from_str(raw)?)?
+            .unwrap_or_else(|| toml::Value::Table(Context::new())),
+        "toml" => toml::Value::Table(toml::from_str(raw)?),
+        "csv" => csv_rows(raw)?,
+        _ => bail!("Unknown data file type `{extension}`."),
+    })
+}
+
+/// Convert a JSON value into a TOML one. Nulls are dropped, since TOML has no
+/// way to represent them.
+fn json_value(value: serde_json::Value) -> Option<toml::Value> {
+    Some(match value {
+        serde_json::Value::Null => return None,
+        serde_json::Value::Bool(b) => toml::Value::Boolean(b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => toml::Value::Integer(i),
+            None => toml::Value::Float(n.as_f64().unwrap_or_default()),
+        },
+        serde_json::Value::String(s) => toml::Value::String(s),
+        serde_json::Value::Array(array) => toml::Value::Array(array.into_iter().filter_map(json_value).collect()),
+        serde_json::Value::Object(map) => toml::Value::Table(
+            map.into_iter()
+                .filter_map(|(key, value)| Some((key, json_value(value)?)))
+                .collect()
+        ),
+    })
+}
+
+/// The rows of a CSV file as tables of strings keyed by its header row.
+fn csv_rows(raw: &str) -> Result<toml::Value> {
+    let mut reader = csv::Reader::from_reader(raw.as_bytes());
+    let headers = reader.headers()?.clone();
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let row = headers.iter()
+            .zip(record?.iter())
+            .map(|(header, field)| (header.to_owned(), toml::Value::String(field.to_owned())))
+            .collect();
+        rows.push(toml::Value::Table(row));
+    }
+    Ok(toml::Value::Array(rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A fresh data directory holding `files`, as `(path, contents)` pairs.
+    fn data_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tinytemple-data-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (path, contents) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    fn load_files(name: &str, files: &[(&str, &str)]) -> Result<Context> {
+        let dir = data_dir(name, files);
+        let data = load(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        data
+    }
+
+    #[test]
+    fn nests_by_directory() {
+        let data = load_files("nests", &[
+            ("site.toml", "title = \"Example\""),
+            ("team/members.yaml", "- Ann\n- Bob\n"),
+            ("team/leads/first.json", "{\"name\": \"Ann\", \"nickname\": null}"),
+            ("team.json", "{\"name\": \"Crew\"}"),
+            ("notes.txt", "Not data."),
+            (".hidden.json", "[]"),
+        ]).unwrap();
+
+        assert_eq!(data["site"]["title"].as_str(), Some("Example"));
+        assert_eq!(data["team"]["members"], toml::Value::Array(vec!["Ann".into(), "Bob".into()]));
+        assert_eq!(data["team"]["leads"]["first"]["name"].as_str(), Some("Ann"));
+        assert!(data["team"]["leads"]["first"].get("nickname").is_none());
+        assert_eq!(data["team"]["name"].as_str(), Some("Crew"));
+        assert_eq!(data.len(), 2);
+    }
+
+    #[test]
+    fn missing_directories_have_no_data() {
+        let dir = std::env::temp_dir().join(format!("tinytemple-data-{}-missing", std::process::id()));
+        assert!(load(&dir).unwrap().is_empty());
+    }
+
+    #[test]
+    fn keys_csv_rows_by_header() {
+        let data = load_files("csv", &[("products.csv", "name,price\nWidget,3\n\"Big, red\",10\n")]).unwrap();
+        let rows = data["products"].as_array().unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["name"].as_str(), Some("Widget"));
+        assert_eq!(rows[0]["price"].as_str(), Some("3"));
+        assert_eq!(rows[1]["name"].as_str(), Some("Big, red"));
+    }
+
+    #[test]
+    fn rejects_name_conflicts() {
+        let error = load_files("stems", &[("links.json", "[1]"), ("links.yaml", "- 2")]).unwrap_err();
+        assert!(error.to_string().contains("has the same name as another data file or directory"), "{error}");
+
+        let error = load_files("dirs", &[("team/members.yaml", "- Ann"), ("team.csv", "name\nBob\n")]).unwrap_err();
+        assert!(error.to_string().contains("team.csv has the same name"), "{error}");
+    }
+
+    #[test]
+    fn reports_the_file_that_fails_to_parse() {
+        let error = load_files("broken", &[("broken.json", "{")]).unwrap_err();
+        assert!(error.to_string().starts_with("Unable to parse"), "{error}");
+        assert!(error.to_string().contains("broken.json"), "{error}");
+    }
+}
@@ -0,0 +1,279 @@
+//! RSS 2.0 and Atom feeds.
+//!
+//! Feeds are enabled by a `[feed]` table in the configuration, and require
+//! `base_url` to be set since feed readers need absolute links:
+//!
+//! ```toml
+//! [feed]
+//! formats = ["rss", "atom"] # Both by default.
+//! content = "summary"       # Or "full", the default.
+//! limit = 20                # Newest entries per feed, 20 by default.
+//! ```
+//!
+//! Every collection gets `<collection>/rss.xml` and `<collection>/atom.xml`, and
+//! every taxonomy term gets the same files next to its term page.
+
+use chrono::{DateTime, FixedOffset, Utc};
+use crate::{content::Page, escape, permalink, Context};
+
+/// Number of entries in a feed when the configuration does not set `limit`.
+pub const DEFAULT_LIMIT: usize = 20;
+
+/// A feed file format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Rss,
+    Atom,
+}
+
+impl Format {
+    /// Name of the file the feed is written to.
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Format::Rss => "rss.xml",
+            Format::Atom => "atom.xml",
+        }
+    }
+}
+
+/// Feed settings read from the `[feed]` configuration table.
+#[derive(Debug, Clone)]
+pub struct FeedConfig {
+    /// Formats to write for every feed.
+    pub formats: Vec<Format>,
+
+    /// Whether entries carry the full content rather than the summary.
+    pub full_content: bool,
+
+    /// Maximum number of entries per feed.
+    pub limit: usize,
+}
+
+impl FeedConfig {
+    /// Read the feed settings, or `None` if feeds are not enabled.
+    pub fn from_context(ctx: &Context) -> Option<Self> {
+        let table = ctx.get("feed")?.as_table()?;
+
+        let formats = match table.get("formats") {
+            Some(toml::Value::Array(formats)) => formats.iter()
+                .filter_map(|format| match format.as_str() {
+                    Some("rss") => Some(Format::Rss),
+                    Some("atom") => Some(Format::Atom),
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![Format::Rss, Format::Atom],
+        };
+        let full_content = !matches!(table.get("content"), Some(toml::Value::String(c)) if c == "summary");
+        let limit = match table.get("limit") {
+            Some(toml::Value::Integer(n)) if *n > 0 => *n as usize,
+            _ => DEFAULT_LIMIT,
+        };
+
+        Some(Self { formats, full_content, limit })
+    }
+}
+
+/// A single feed, listing its pages newest first.
+#[derive(Debug, Clone)]
+pub struct Feed<'a> {
+    /// Title of the feed.
+    pub title: String,
+
+    /// Site-relative URL of the HTML page the feed mirrors.
+    pub link: String,
+
+    /// Output directory of the feed, relative to the output directory.
+    pub dir: String,
+
+    /// Entries of the feed.
+    pub pages: Vec<&'a Page>,
+}
+
+impl Feed<'_> {
+    /// Output name of the feed in `format`, relative to the output directory.
+    pub fn output_name(&self, format: Format) -> String {
+        format!("{}/{}", self.dir, format.file_name())
+    }
+
+    /// Render the feed in `format`.
+    pub fn render(&self, format: Format, config: &FeedConfig, ctx: &Context) -> String {
+        match format {
+            Format::Rss => self.rss(config, ctx),
+            Format::Atom => self.atom(config, ctx),
+        }
+    }
+
+    fn rss(&self, config: &FeedConfig, ctx: &Context) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n<channel>\n");
+        xml.push_str(&format!("<title>{}</title>\n", escape(&self.title)));
+        xml.push_str(&format!("<link>{}</link>\n", escape(&self.absolute(ctx, &self.link))));
+        let self_url = self.absolute(ctx, &format!("/{}", self.output_name(Format::Rss)));
+        xml.push_str(&format!("<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n", escape(&self_url)));
+        let description = ctx.get("description").and_then(|d| d.as_str()).unwrap_or(&self.title);
+        xml.push_str(&format!("<description>{}</description>\n", escape(description)));
+        xml.push_str(&format!("<lastBuildDate>{}</lastBuildDate>\n", self.updated().to_rfc2822()));
+
+        for page in self.pages.iter().take(config.limit) {
+            let link = self.absolute(ctx, &page.url());
+            xml.push_str("<item>\n");
+            xml.push_str(&format!("<title>{}</title>\n", escape(title(page))));
+            xml.push_str(&format!("<link>{}</link>\n", escape(&link)));
+            xml.push_str(&format!("<guid>{}</guid>\n", escape(&link)));
+            if let Some(date) = page.datetime() {
+                xml.push_str(&format!("<pubDate>{}</pubDate>\n", date.to_rfc2822()));
+            }
+            if let Some(author) = author(page, ctx) {
+                xml.push_str(&format!("<author>{}</author>\n", escape(author)));
+            }
+            xml.push_str(&format!("<description>{}</description>\n", escape(body(page, config))));
+            xml.push_str("</item>\n");
+        }
+
+        xml.push_str("</channel>\n</rss>\n");
+        xml
+    }
+
+    fn atom(&self, config: &FeedConfig, ctx: &Context) -> String {
+        let self_url = self.absolute(ctx, &format!("/{}", self.output_name(Format::Atom)));
+
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+        xml.push_str(&format!("<title>{}</title>\n", escape(&self.title)));
+        xml.push_str(&format!("<id>{}</id>\n", escape(&self_url)));
+        xml.push_str(&format!("<link href=\"{}\" rel=\"self\"/>\n", escape(&self_url)));
+        xml.push_str(&format!("<link href=\"{}\"/>\n", escape(&self.absolute(ctx, &self.link))));
+        xml.push_str(&format!("<updated>{}</updated>\n", self.updated().to_rfc3339()));
+        if let Some(author) = ctx.get("author").and_then(|a| a.as_str()) {
+            xml.push_str(&format!("<author><name>{}</name></author>\n", escape(author)));
+        }
+
+        for page in self.pages.iter().take(config.limit) {
+            let link = self.absolute(ctx, &page.url());
+            xml.push_str("<entry>\n");
+            xml.push_str(&format!("<title>{}</title>\n", escape(title(page))));
+            xml.push_str(&format!("<id>{}</id>\n", escape(&link)));
+            xml.push_str(&format!("<link href=\"{}\"/>\n", escape(&link)));
+            let updated = page.datetime().unwrap_or_else(|| self.updated());
+            xml.push_str(&format!("<updated>{}</updated>\n", updated.to_rfc3339()));
+            if let Some(author) = page.front_matter.get("author").and_then(|a| a.as_str()) {
+                xml.push_str(&format!("<author><name>{}</name></author>\n", escape(author)));
+            }
+            let kind = if config.full_content { "content" } else { "summary" };
+            xml.push_str(&format!("<{kind} type=\"html\">{}</{kind}>\n", escape(body(page, config))));
+            xml.push_str("</entry>\n");
+        }
+
+        xml.push_str("</feed>\n");
+        xml
+    }
+
+    /// When the feed last changed: the date of its newest entry, or now.
+    fn updated(&self) -> DateTime<FixedOffset> {
+        self.pages.iter()
+            .filter_map(|page| page.datetime())
+            .max()
+            .unwrap_or_else(|| Utc::now().fixed_offset())
+    }
+
+    fn absolute(&self, ctx: &Context, url: &str) -> String {
+        permalink(ctx, url).unwrap_or_else(|| url.to_owned())
+    }
+}
+
+fn title(page: &Page) -> &str {
+    page.front_matter.get("title").and_then(|t| t.as_str()).unwrap_or(&page.name)
+}
+
+fn author<'a>(page: &'a Page, ctx: &'a Context) -> Option<&'a str> {
+    page.front_matter.get("author").or_else(|| ctx.get("author")).and_then(|a| a.as_str())
+}
+
+fn body<'a>(page: &'a Page, config: &FeedConfig) -> &'a str {
+    if config.full_content { &page.content } else { &page.summary }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(name: &str, title: &str, date: &str) -> Page {
+        let front_matter: Context = toml::from_str(&format!("title = {title:?}\ndate = {date:?}")).unwrap();
+        Page {
+            name: name.to_owned(),
+            front_matter,
+            content: "<p>Fish & chips</p>".to_owned(),
+            summary: "<p>Fish</p>".to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn config(feed: &str) -> FeedConfig {
+        let ctx: Context = toml::from_str(&format!("[feed]\n{feed}")).unwrap();
+        FeedConfig::from_context(&ctx).unwrap()
+    }
+
+    #[test]
+    fn reads_the_configuration() {
+        let config = config("");
+        assert_eq!(config.formats, [Format::Rss, Format::Atom]);
+        assert!(config.full_content);
+        assert_eq!(config.limit, DEFAULT_LIMIT);
+
+        let config = self::config("formats = [\"atom\"]\ncontent = \"summary\"\nlimit = 2");
+        assert_eq!(config.formats, [Format::Atom]);
+        assert!(!config.full_content);
+        assert_eq!(config.limit, 2);
+
+        assert_eq!(self::config("limit = 0").limit, DEFAULT_LIMIT);
+        assert!(FeedConfig::from_context(&Context::new()).is_none());
+    }
+
+    #[test]
+    fn escapes_text_and_content() {
+        let pages = [page("posts/first", "Salt & <vinegar>", "2024-06-01")];
+        let feed = Feed {
+            title: "Tom & Jerry".to_owned(),
+            link: "/posts/".to_owned(),
+            dir: "posts".to_owned(),
+            pages: pages.iter().collect(),
+        };
+        let ctx: Context = toml::from_str("base_url = \"https://example.com\"").unwrap();
+
+        for format in [Format::Rss, Format::Atom] {
+            let xml = feed.render(format, &config(""), &ctx);
+            assert!(xml.contains("<title>Tom &amp; Jerry</title>"), "{xml}");
+            assert!(xml.contains("<title>Salt &amp; &lt;vinegar&gt;</title>"), "{xml}");
+            assert!(xml.contains("&lt;p&gt;Fish &amp; chips&lt;/p&gt;"), "{xml}");
+            assert!(!xml.contains("<p>"), "{xml}");
+        }
+    }
+
+    #[test]
+    fn limits_the_number_of_entries() {
+        let pages = [
+            page("posts/c", "C", "2024-06-03"),
+            page("posts/b", "B", "2024-06-02"),
+            page("posts/a", "A", "2024-06-01"),
+        ];
+        let feed = Feed {
+            title: "Posts".to_owned(),
+            link: "/posts/".to_owned(),
+            dir: "posts".to_owned(),
+            pages: pages.iter().collect(),
+        };
+        let config = config("limit = 2");
+
+        let rss = feed.render(Format::Rss, &config, &Context::new());
+        assert_eq!(rss.matches("<item>").count(), 2);
+        assert!(rss.contains("<title>C</title>") && rss.contains("<title>B</title>"));
+        assert!(!rss.contains("<title>A</title>"));
+
+        let atom = feed.render(Format::Atom, &config, &Context::new());
+        assert_eq!(atom.matches("<entry>").count(), 2);
+        assert!(!atom.contains("<title>A</title>"));
+    }
+}
@@ -0,0 +1,409 @@
+//! Helpers registered on every template engine.
+//!
+//! Each helper computes a value from its arguments, so helpers compose as
+//! subexpressions: `{{#each (first (sort_by pages "title") 5)}}`.
+
+use std::{cmp::Ordering, collections::BTreeMap, fmt::Write};
+use handlebars::{Context, Handlebars, Helper, HelperDef, JsonValue, RenderContext, RenderError, ScopedJson};
+use crate::content::parse_date;
+
+/// Format of `date` when the template does not give one.
+pub const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Ending of text shortened by `truncate` when the template does not give one.
+pub const DEFAULT_ELLIPSIS: &str = "…";
+
+type Hash<'a> = BTreeMap<&'a str, &'a JsonValue>;
+type Function = fn(&[&JsonValue], &Hash) -> Result<JsonValue, String>;
+
+/// The helpers, by name.
+const HELPERS: &[(&str, Function)] = &[
+    ("date", date),
+    ("slugify", slugify),
+    ("truncate", truncate),
+    ("upper", upper),
+    ("lower", lower),
+    ("default", default),
+    ("add", add),
+    ("sub", sub),
+    ("mul", mul),
+    ("div", div),
+    ("mod", modulo),
+    ("sort_by", sort_by),
+    ("where", where_),
+    ("filter", filter),
+    ("first", first),
+    ("last", last),
+    ("json", json),
+];
+
+/// Register every helper on `engine`. Helpers registered later under the same
+/// name replace these, and variables of the same name are still reachable as
+/// long as they are used without arguments.
+pub fn register(engine: &mut Handlebars) {
+    for (name, function) in HELPERS {
+        engine.register_helper(name, Box::new(Builtin { name, function: *function }));
+    }
+}
+
+/// Adapts a [`Function`] to a Handlebars helper.
+struct Builtin {
+    name: &'static str,
+    function: Function,
+}
+
+impl HelperDef for Builtin {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        ctx: &'rc Context,
+        rc: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        // Without arguments, `{{date}}` is the variable rather than the helper.
+        if h.params().is_empty() && h.hash().is_empty() {
+            return rc.evaluate(ctx, self.name);
+        }
+        let params: Vec<&JsonValue> = h.params().iter().map(|param| param.value()).collect();
+        let hash: Hash = h.hash().iter().map(|(key, value)| (*key, value.value())).collect();
+        match (self.function)(&params, &hash) {
+            Ok(value) => Ok(ScopedJson::Derived(value)),
+            Err(e) => Err(RenderError::new(format!("`{}` helper: {e}", self.name))),
+        }
+    }
+}
+
+/// `{{date date "%B %e, %Y"}}` formats a date with a strftime format string,
+/// `%Y-%m-%d` by default. Missing dates are left empty.
+fn date(params: &[&JsonValue], _: &Hash) -> Result<JsonValue, String> {
+    let text = match param(params, 0)? {
+        JsonValue::Null => return Ok(JsonValue::Null),
+        value => text(value),
+    };
+    let format = match params.get(1) {
+        Some(format) => format.as_str().ok_or("the format must be a string.")?,
+        None => DEFAULT_DATE_FORMAT,
+    };
+    let datetime = parse_date(&text).ok_or_else(|| format!("`{text}` is not a date."))?;
+    let mut formatted = String::new();
+    match write!(formatted, "{}", datetime.format(format)) {
+        Ok(_) => Ok(JsonValue::String(formatted)),
+        Err(_) => Err(format!("`{format}` is not a valid date format.")),
+    }
+}
+
+/// `{{slugify title}}` turns text into a slug for URLs.
+fn slugify(params: &[&JsonValue], _: &Hash) -> Result<JsonValue, String> {
+    Ok(JsonValue::String(crate::slugify(&text(param(params, 0)?))))
+}
+
+/// `{{truncate summary 100 end="..."}}` shortens text to at most as many
+/// characters, followed by `end`, `…` by default.
+fn truncate(params: &[&JsonValue], hash: &Hash) -> Result<JsonValue, String> {
+    let text = text(param(params, 0)?);
+    let length = param(params, 1)?.as_u64().ok_or("the length must be a whole number.")? as usize;
+    let end = hash.get("end").map(|end| self::text(end)).unwrap_or_else(|| DEFAULT_ELLIPSIS.to_owned());
+    if text.chars().count() <= length {
+        return Ok(JsonValue::String(text));
+    }
+    let shortened: String = text.chars().take(length).collect();
+    Ok(JsonValue::String(format!("{}{end}", shortened.trim_end())))
+}
+
+/// `{{upper title}}` uppercases text.
+fn upper(params: &[&JsonValue], _: &Hash) -> Result<JsonValue, String> {
+    Ok(JsonValue::String(text(param(params, 0)?).to_uppercase()))
+}
+
+/// `{{lower title}}` lowercases text.
+fn lower(params: &[&JsonValue], _: &Hash) -> Result<JsonValue, String> {
+    Ok(JsonValue::String(text(param(params, 0)?).to_lowercase()))
+}
+
+/// `{{default author "Anonymous"}}` is the first value unless it is missing
+/// or empty, and the second otherwise.
+fn default(params: &[&JsonValue], _: &Hash) -> Result<JsonValue, String> {
+    match param(params, 0)? {
+        JsonValue::Null => Ok(param(params, 1)?.clone()),
+        JsonValue::String(text) if text.is_empty() => Ok(param(params, 1)?.clone()),
+        value => Ok(value.clone()),
+    }
+}
+
+/// `{{add a b}}`, along with `sub`, `mul`, `div` and `mod`, do arithmetic.
+/// Whole numbers stay whole unless division leaves a remainder.
+fn add(params: &[&JsonValue], _: &Hash) -> Result<JsonValue, String> {
+    arithmetic(params, i64::checked_add, |a, b| a + b)
+}
+
+fn sub(params: &[&JsonValue], _: &Hash) -> Result<JsonValue, String> {
+    arithmetic(params, i64::checked_sub, |a, b| a - b)
+}
+
+fn mul(params: &[&JsonValue], _: &Hash) -> Result<JsonValue, String> {
+    arithmetic(params, i64::checked_mul, |a, b| a * b)
+}
+
+fn div(params: &[&JsonValue], _: &Hash) -> Result<JsonValue, String> {
+    if number(param(params, 1)?)? == 0.0 {
+        return Err("division by zero.".to_owned());
+    }
+    let exact = |a: i64, b: i64| (a.checked_rem(b)? == 0).then(|| a.checked_div(b)).flatten();
+    arithmetic(params, exact, |a, b| a / b)
+}
+
+fn modulo(params: &[&JsonValue], _: &Hash) -> Result<JsonValue, String> {
+    if number(param(params, 1)?)? == 0.0 {
+        return Err("division by zero.".to_owned());
+    }
+    arithmetic(params, i64::checked_rem, |a, b| a % b)
+}
+
+/// `{{#each (sort_by pages "date" reverse=true)}}` sorts objects by the value
+/// at a path. Numbers sort numerically, and objects missing the value last.
+fn sort_by(params: &[&JsonValue], hash: &Hash) -> Result<JsonValue, String> {
+    let items = array(param(params, 0)?)?;
+    let path = param(params, 1)?.as_str().ok_or("the key must be a string.")?;
+    let reverse = hash.get("reverse").is_some_and(|reverse| truthy(reverse));
+
+    let mut sorted = items.to_vec();
+    sorted.sort_by(|a, b| {
+        let (a, b) = (lookup(a, path), lookup(b, path));
+        match (a, b) {
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(_), None) => Ordering::Less,
+            (Some(a), Some(b)) if reverse => compare(b, a),
+            (Some(a), Some(b)) => compare(a, b),
+        }
+    });
+    Ok(JsonValue::Array(sorted))
+}
+
+/// `{{#each (where pages "tags" "rust")}}` keeps the objects whose value at a
+/// path equals the given one, or is an array containing it.
+fn where_(params: &[&JsonValue], _: &Hash) -> Result<JsonValue, String> {
+    let items = array(param(params, 0)?)?;
+    let path = param(params, 1)?.as_str().ok_or("the key must be a string.")?;
+    let expected = param(params, 2)?;
+    let kept = items.iter()
+        .filter(|item| match lookup(item, path) {
+            Some(JsonValue::Array(values)) => values.contains(expected),
+            Some(value) => value == expected,
+            None => false,
+        })
+        .cloned()
+        .collect();
+    Ok(JsonValue::Array(kept))
+}
+
+/// `{{#each (filter pages "featured")}}` keeps the objects whose value at a
+/// path is truthy.
+fn filter(params: &[&JsonValue], _: &Hash) -> Result<JsonValue, String> {
+    let items = array(param(params, 0)?)?;
+    let path = param(params, 1)?.as_str().ok_or("the key must be a string.")?;
+    let kept = items.iter()
+        .filter(|item| lookup(item, path).is_some_and(truthy))
+        .cloned()
+        .collect();
+    Ok(JsonValue::Array(kept))
+}
+
+/// `{{first pages}}` is the first item of an array or character of a string,
+/// and `{{first pages 3}}` the first three.
+fn first(params: &[&JsonValue], _: &Hash) -> Result<JsonValue, String> {
+    take(params, |length, count| 0..count.min(length))
+}
+
+/// `{{last pages}}` is the last item of an array or character of a string,
+/// and `{{last pages 3}}` the last three.
+fn last(params: &[&JsonValue], _: &Hash) -> Result<JsonValue, String> {
+    take(params, |length, count| length.saturating_sub(count)..length)
+}
+
+/// `{{json page}}` writes a value as JSON, indented if `pretty=true`.
+fn json(params: &[&JsonValue], hash: &Hash) -> Result<JsonValue, String> {
+    let value = param(params, 0)?;
+    if hash.get("pretty").is_some_and(|pretty| truthy(pretty)) {
+        Ok(JsonValue::String(format!("{value:#}")))
+    } else {
+        Ok(JsonValue::String(value.to_string()))
+    }
+}
+
+fn param<'a>(params: &[&'a JsonValue], index: usize) -> Result<&'a JsonValue, String> {
+    params.get(index).copied().ok_or_else(|| format!("missing argument {}.", index + 1))
+}
+
+/// A value as template text: strings as they are, missing values as nothing
+/// and anything else as JSON.
+fn text(value: &JsonValue) -> String {
+    match value {
+        JsonValue::String(text) => text.clone(),
+        JsonValue::Null => String::new(),
+        value => value.to_string(),
+    }
+}
+
+fn number(value: &JsonValue) -> Result<f64, String> {
+    value.as_f64().ok_or_else(|| format!("`{value}` is not a number."))
+}
+
+fn array(value: &JsonValue) -> Result<&[JsonValue], String> {
+    match value {
+        JsonValue::Array(items) => Ok(items),
+        JsonValue::Null => Ok(&[]),
+        value => Err(format!("`{value}` is not an array.")),
+    }
+}
+
+/// Whether a value counts as true, as in `{{#if}}`.
+fn truthy(value: &JsonValue) -> bool {
+    match value {
+        JsonValue::Null => false,
+        JsonValue::Bool(b) => *b,
+        JsonValue::Number(n) => n.as_f64() != Some(0.0),
+        JsonValue::String(s) => !s.is_empty(),
+        JsonValue::Array(a) => !a.is_empty(),
+        JsonValue::Object(_) => true,
+    }
+}
+
+/// The value at a dotted `path` within `value`.
+fn lookup<'a>(value: &'a JsonValue, path: &str) -> Option<&'a JsonValue> {
+    path.split('.').try_fold(value, |value, key| match value {
+        JsonValue::Array(items) => items.get(key.parse::<usize>().ok()?),
+        value => value.get(key),
+    })
+}
+
+fn compare(a: &JsonValue, b: &JsonValue) -> Ordering {
+    match (a.as_f64(), b.as_f64()) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        _ => text(a).cmp(&text(b)),
+    }
+}
+
+/// Apply an operation to two numbers, on whole numbers if both are and the
+/// result is one, and on floating point ones otherwise.
+fn arithmetic(
+    params: &[&JsonValue],
+    whole: fn(i64, i64) -> Option<i64>,
+    float: fn(f64, f64) -> f64,
+) -> Result<JsonValue, String> {
+    let (a, b) = (param(params, 0)?, param(params, 1)?);
+    if let (Some(a), Some(b)) = (a.as_i64(), b.as_i64()) {
+        if let Some(result) = whole(a, b) {
+            return Ok(JsonValue::from(result));
+        }
+    }
+    Ok(JsonValue::from(float(number(a)?, number(b)?)))
+}
+
+/// The items or characters of the first parameter within `range(length,
+/// count)`. Without a count, the single item or character.
+fn take(params: &[&JsonValue], range: fn(usize, usize) -> std::ops::Range<usize>) -> Result<JsonValue, String> {
+    let value = param(params, 0)?;
+    let count = match params.get(1) {
+        Some(count) => Some(count.as_u64().ok_or("the count must be a whole number.")? as usize),
+        None => None,
+    };
+    match (value, count) {
+        (JsonValue::Array(items), Some(count)) => Ok(JsonValue::Array(items[range(items.len(), count)].to_vec())),
+        (JsonValue::Array(items), None) => Ok(items[range(items.len(), 1)].first().cloned().unwrap_or_default()),
+        (JsonValue::String(text), count) => {
+            let chars: Vec<char> = text.chars().collect();
+            Ok(JsonValue::String(chars[range(chars.len(), count.unwrap_or(1))].iter().collect()))
+        },
+        (JsonValue::Null, _) => Ok(JsonValue::Null),
+        (value, _) => Err(format!("`{value}` is not an array or a string.")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn call(function: Function, params: &[JsonValue], hash: &[(&'static str, JsonValue)]) -> Result<JsonValue, String> {
+        let params: Vec<&JsonValue> = params.iter().collect();
+        let hash: Hash = hash.iter().map(|(key, value)| (*key, value)).collect();
+        function(&params, &hash)
+    }
+
+    #[test]
+    fn division_by_zero_fails() {
+        assert!(call(div, &[json!(1), json!(0)], &[]).is_err());
+        assert!(call(div, &[json!(1.5), json!(0.0)], &[]).is_err());
+        assert!(call(modulo, &[json!(7), json!(0)], &[]).is_err());
+        assert_eq!(call(div, &[json!(6), json!(3)], &[]), Ok(json!(2)));
+        assert_eq!(call(div, &[json!(7), json!(2)], &[]), Ok(json!(3.5)));
+        assert_eq!(call(modulo, &[json!(7), json!(3)], &[]), Ok(json!(1)));
+    }
+
+    #[test]
+    fn truncate_counts_characters() {
+        assert_eq!(call(truncate, &[json!("héllo wörld"), json!(5)], &[]), Ok(json!("héllo…")));
+        assert_eq!(call(truncate, &[json!("日本語のテキスト"), json!(3)], &[("end", json!("..."))]), Ok(json!("日本語...")));
+        assert_eq!(call(truncate, &[json!("short"), json!(10)], &[]), Ok(json!("short")));
+    }
+
+    #[test]
+    fn first_and_last_stop_at_the_ends() {
+        let items = json!([1, 2, 3]);
+        assert_eq!(call(first, &[items.clone(), json!(5)], &[]), Ok(json!([1, 2, 3])));
+        assert_eq!(call(last, &[items.clone(), json!(5)], &[]), Ok(json!([1, 2, 3])));
+        assert_eq!(call(last, &[items.clone(), json!(2)], &[]), Ok(json!([2, 3])));
+        assert_eq!(call(first, &[items], &[]), Ok(json!(1)));
+        assert_eq!(call(first, &[json!([])], &[]), Ok(JsonValue::Null));
+        assert_eq!(call(last, &[json!("ünï"), json!(9)], &[]), Ok(json!("ünï")));
+        assert_eq!(call(first, &[json!("ünï")], &[]), Ok(json!("ü")));
+    }
+
+    #[test]
+    fn sort_by_puts_missing_keys_last() {
+        let pages = json!([{"n": 2}, {}, {"n": 10}, {"n": 1}]);
+        assert_eq!(
+            call(sort_by, &[pages.clone(), json!("n")], &[]),
+            Ok(json!([{"n": 1}, {"n": 2}, {"n": 10}, {}])),
+        );
+        assert_eq!(
+            call(sort_by, &[pages, json!("n")], &[("reverse", json!(true))]),
+            Ok(json!([{"n": 10}, {"n": 2}, {"n": 1}, {}])),
+        );
+    }
+
+    #[test]
+    fn where_matches_array_members() {
+        let pages = json!([
+            {"title": "a", "tags": ["rust", "web"]},
+            {"title": "b", "tags": ["web"]},
+            {"title": "c", "tags": "rust"},
+            {"title": "d"},
+        ]);
+        assert_eq!(
+            call(where_, &[pages, json!("tags"), json!("rust")], &[]),
+            Ok(json!([{"title": "a", "tags": ["rust", "web"]}, {"title": "c", "tags": "rust"}])),
+        );
+    }
+
+    #[test]
+    fn date_formats() {
+        assert_eq!(call(date, &[json!("2024-03-01T10:00:00Z")], &[]), Ok(json!("2024-03-01")));
+        assert_eq!(call(date, &[json!("2024-03-01"), json!("%B %e, %Y")], &[]), Ok(json!("March  1, 2024")));
+        assert!(call(date, &[json!("2024-03-01"), json!("%Q")], &[]).is_err());
+        assert!(call(date, &[json!("yesterday")], &[]).is_err());
+        assert_eq!(call(date, &[JsonValue::Null], &[]), Ok(JsonValue::Null));
+    }
+
+    #[test]
+    fn helpers_without_arguments_are_variables() {
+        let mut engine = Handlebars::new();
+        register(&mut engine);
+        let data = json!({"date": "2024-03-01T10:00:00Z", "first": "Ada"});
+        assert_eq!(
+            engine.render_template("{{date}} {{date date}} {{first}}", &data).unwrap(),
+            "2024-03-01T10:00:00Z 2024-03-01 Ada",
+        );
+    }
+}
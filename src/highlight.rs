@@ -0,0 +1,304 @@
+//! Syntax highlighting of fenced code blocks.
+//!
+//! Code blocks are highlighted at build time when the configuration has a
+//! `[highlight]` table:
+//!
+//! ```toml
+//! [highlight]
+//! theme = "base16-ocean.dark"  # The default.
+//! style = "classes"            # Or "inline", the default.
+//! stylesheet = "highlight.css" # Where the "classes" stylesheet is written.
+//! line_numbers = true          # Number the lines of every block.
+//! ```
+//!
+//! With `style = "inline"` every token carries its colours in a `style`
+//! attribute. With `style = "classes"` tokens carry `hl-` prefixed classes of
+//! their scopes instead, and a stylesheet for the theme is written to the
+//! output directory.
+//!
+//! Options may follow the language in the info string of a block, separated by
+//! commas: `linenos` numbers its lines, and `hl_lines` marks lines or ranges of
+//! lines, as in ```` ```rust,linenos,hl_lines=1 3-5 ````.
+
+use std::ops::RangeInclusive;
+use color_eyre::eyre::{Result, eyre};
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{Color, Theme, ThemeSet},
+    html::{ClassStyle, IncludeBackground},
+    parsing::{BasicScopeStackOp, ParseState, Scope, ScopeStack, SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+};
+use crate::{escape, Context};
+
+/// Theme used when the configuration does not name one.
+pub const DEFAULT_THEME: &str = "base16-ocean.dark";
+
+/// Output name of the stylesheet when the configuration does not set one.
+pub const DEFAULT_STYLESHEET: &str = "highlight.css";
+
+/// Prefix of the classes given to tokens.
+const CLASS_PREFIX: &str = "hl-";
+
+/// How highlighted tokens are styled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// Colours in `style` attributes.
+    Inline,
+
+    /// Classes styled by a separate stylesheet.
+    Classes,
+}
+
+/// Highlights code blocks according to the `[highlight]` configuration table.
+#[derive(Debug)]
+pub struct Highlighter {
+    syntaxes: SyntaxSet,
+    theme: Theme,
+
+    /// How tokens are styled.
+    pub style: Style,
+
+    /// Output name of the stylesheet, when styling with classes.
+    pub stylesheet: String,
+
+    /// Whether every block has its lines numbered.
+    pub line_numbers: bool,
+}
+
+impl Highlighter {
+    /// Read the highlighting settings, or `None` if highlighting is not
+    /// enabled. Unknown themes and styles are returned as errors.
+    pub fn from_context(ctx: &Context) -> Option<Result<Self>> {
+        let table = ctx.get("highlight")?.as_table()?;
+
+        let theme_name = table.get("theme").and_then(|t| t.as_str()).unwrap_or(DEFAULT_THEME);
+        let mut themes = ThemeSet::load_defaults();
+        let theme = match themes.themes.remove(theme_name) {
+            Some(theme) => theme,
+            None => {
+                let known = themes.themes.keys().cloned().collect::<Vec<_>>().join(", ");
+                return Some(Err(eyre!("Unknown highlighting theme `{theme_name}`. Known themes are: {known}.")));
+            }
+        };
+        let style = match table.get("style").and_then(|s| s.as_str()) {
+            None | Some("inline") => Style::Inline,
+            Some("classes") => Style::Classes,
+            Some(other) => return Some(Err(eyre!("Unknown highlighting style `{other}`."))),
+        };
+        let stylesheet = table.get("stylesheet")
+            .and_then(|s| s.as_str())
+            .unwrap_or(DEFAULT_STYLESHEET)
+            .trim_start_matches('/')
+            .to_owned();
+        let line_numbers = matches!(table.get("line_numbers"), Some(toml::Value::Boolean(true)));
+
+        Some(Ok(Self {
+            syntaxes: SyntaxSet::load_defaults_newlines(),
+            theme,
+            style,
+            stylesheet,
+            line_numbers,
+        }))
+    }
+
+    /// Highlight `code` from a block with the info string `info`.
+    pub fn highlight(&self, info: &str, code: &str) -> String {
+        let mut options = info.split(',').map(str::trim);
+        let lang = options.next().unwrap_or_default();
+        let mut line_numbers = self.line_numbers;
+        let mut marked = Vec::new();
+        for option in options {
+            match option.split_once('=') {
+                Some(("hl_lines", ranges)) => marked.extend(ranges.split_whitespace().filter_map(line_range)),
+                _ if option == "linenos" => line_numbers = true,
+                _ => (),
+            }
+        }
+
+        let syntax = self.syntaxes.find_syntax_by_token(lang)
+            .unwrap_or_else(|| self.syntaxes.find_syntax_plain_text());
+        let lines = match self.style {
+            Style::Inline => self.inline_lines(syntax, code),
+            Style::Classes => self.classed_lines(syntax, code),
+        };
+
+        let width = lines.len().to_string().len();
+        let mut html = match self.style {
+            Style::Inline => {
+                let background = self.theme.settings.background.map(css_color).unwrap_or_default();
+                format!("<pre class=\"highlight\" style=\"background-color:{background};\">")
+            },
+            Style::Classes => format!("<pre class=\"highlight {CLASS_PREFIX}code\">"),
+        };
+        match lang {
+            "" => html.push_str("<code>"),
+            lang => html.push_str(&format!("<code class=\"language-{0}\" data-lang=\"{0}\">", escape(lang))),
+        }
+
+        for (index, line) in lines.iter().enumerate() {
+            let number = index + 1;
+            let mark = marked.iter().any(|range| range.contains(&number));
+            if mark {
+                match self.style {
+                    Style::Inline => {
+                        let background = self.theme.settings.line_highlight
+                            .map(css_color)
+                            .unwrap_or_else(|| "rgba(255,255,255,0.1)".to_owned());
+                        html.push_str(&format!("<mark style=\"background-color:{background};color:inherit;\">"));
+                    },
+                    Style::Classes => html.push_str(&format!("<mark class=\"{CLASS_PREFIX}line\">")),
+                }
+            }
+            if line_numbers {
+                match self.style {
+                    Style::Inline => html.push_str("<span class=\"lineno\" style=\"user-select:none;opacity:0.5;padding-right:1em;\">"),
+                    Style::Classes => html.push_str(&format!("<span class=\"{CLASS_PREFIX}lineno\">")),
+                }
+                html.push_str(&format!("{number:>width$}</span>"));
+            }
+            html.push_str(line);
+            if mark {
+                html.push_str("</mark>");
+            }
+        }
+
+        html.push_str("</code></pre>\n");
+        html
+    }
+
+    /// The stylesheet for highlighted tokens, when styling with classes.
+    pub fn css(&self) -> Option<String> {
+        if self.style != Style::Classes {
+            return None;
+        }
+        let mut css = syntect::html::css_for_theme_with_class_style(
+            &self.theme,
+            ClassStyle::SpacedPrefixed { prefix: CLASS_PREFIX },
+        ).ok()?;
+        let line = self.theme.settings.line_highlight.map(css_color).unwrap_or_else(|| "rgba(255,255,255,0.1)".to_owned());
+        css.push_str(&format!(".{CLASS_PREFIX}line {{\n background-color: {line};\n color: inherit;\n}}\n"));
+        css.push_str(&format!(".{CLASS_PREFIX}lineno {{\n user-select: none;\n opacity: 0.5;\n padding-right: 1em;\n}}\n"));
+        Some(css)
+    }
+
+    /// Lines of `code` as HTML with inline styles, each ending in its newline.
+    fn inline_lines(&self, syntax: &SyntaxReference, code: &str) -> Vec<String> {
+        let mut highlighter = HighlightLines::new(syntax, &self.theme);
+        let background = self.theme.settings.background.unwrap_or(Color::BLACK);
+        LinesWithEndings::from(code)
+            .map(|line| {
+                highlighter.highlight_line(line, &self.syntaxes)
+                    .ok()
+                    .and_then(|regions| {
+                        syntect::html::styled_line_to_highlighted_html(&regions, IncludeBackground::IfDifferent(background)).ok()
+                    })
+                    .unwrap_or_else(|| escape(line))
+            })
+            .collect()
+    }
+
+    /// Lines of `code` as HTML with classes. Scopes spanning several lines
+    /// are closed at the end of each and reopened at the start of the next,
+    /// so that every line stands on its own.
+    fn classed_lines(&self, syntax: &SyntaxReference, code: &str) -> Vec<String> {
+        let mut state = ParseState::new(syntax);
+        let mut stack = ScopeStack::new();
+        LinesWithEndings::from(code)
+            .map(|line| {
+                let mut html: String = stack.as_slice().iter().map(|scope| open_span(*scope)).collect();
+                let ops = state.parse_line(line, &self.syntaxes).unwrap_or_default();
+                let mut written = 0;
+                for (index, op) in ops {
+                    let index = index.min(line.len());
+                    if index > written {
+                        html.push_str(&escape(&line[written..index]));
+                        written = index;
+                    }
+                    let applied = stack.apply_with_hook(&op, |basic, _| match basic {
+                        BasicScopeStackOp::Push(scope) => html.push_str(&open_span(scope)),
+                        BasicScopeStackOp::Pop => html.push_str("</span>"),
+                    });
+                    if applied.is_err() {
+                        break;
+                    }
+                }
+                html.push_str(&escape(&line[written..]));
+                html.push_str(&"</span>".repeat(stack.len()));
+                html
+            })
+            .collect()
+    }
+}
+
+fn open_span(scope: Scope) -> String {
+    let classes = scope.build_string()
+        .split('.')
+        .map(|atom| format!("{CLASS_PREFIX}{atom}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!("<span class=\"{classes}\">")
+}
+
+/// Parse a line number or an inclusive range of them, like `3` or `3-5`.
+fn line_range(text: &str) -> Option<RangeInclusive<usize>> {
+    match text.split_once('-') {
+        Some((start, end)) => Some(start.trim().parse().ok()?..=end.trim().parse().ok()?),
+        None => {
+            let line = text.trim().parse().ok()?;
+            Some(line..=line)
+        }
+    }
+}
+
+fn css_color(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn highlighter(style: &str) -> Highlighter {
+        let ctx: Context = toml::from_str(&format!("[highlight]\nstyle = \"{style}\"")).unwrap();
+        Highlighter::from_context(&ctx).unwrap().unwrap()
+    }
+
+    #[test]
+    fn parses_line_ranges() {
+        assert_eq!(line_range("3"), Some(3..=3));
+        assert_eq!(line_range("3-5"), Some(3..=5));
+        assert_eq!(line_range(" 3 - 5 "), Some(3..=5));
+        assert_eq!(line_range("3-"), None);
+        assert_eq!(line_range("three"), None);
+    }
+
+    #[test]
+    fn reads_options_from_the_info_string() {
+        let html = highlighter("classes").highlight("txt, linenos, hl_lines=1 3-4", "a\nb\nc\nd\ne\n");
+        assert_eq!(html.matches("<span class=\"hl-lineno\">").count(), 5);
+        let marked: Vec<&str> = html.split("<mark class=\"hl-line\">").skip(1).collect();
+        assert_eq!(marked.len(), 3);
+        assert!(marked[0].starts_with("<span class=\"hl-lineno\">1</span>"));
+        assert!(marked[1].starts_with("<span class=\"hl-lineno\">3</span>"));
+        assert!(marked[2].starts_with("<span class=\"hl-lineno\">4</span>"));
+
+        let html = highlighter("classes").highlight("txt", "a\nb\n");
+        assert!(!html.contains("hl-lineno"));
+        assert!(!html.contains("<mark"));
+    }
+
+    #[test]
+    fn closes_every_span_on_its_own_line() {
+        let highlighter = highlighter("classes");
+        let syntax = highlighter.syntaxes.find_syntax_by_token("rust").unwrap();
+        let lines = highlighter.classed_lines(syntax, "/* a <comment>\n   over lines */\nfn main() {}\n");
+        assert_eq!(lines.len(), 3);
+        for line in &lines {
+            assert_eq!(line.matches("<span").count(), line.matches("</span>").count(), "{line}");
+        }
+        assert!(lines[0].contains("&lt;comment&gt;"));
+        assert!(lines[1].starts_with("<span"));
+        assert!(lines[1].contains("hl-comment"));
+    }
+}
@@ -0,0 +1,1395 @@
+//! A super duper tiny static site generator.
+//!
+//! The whole build pipeline is driven by a [`Site`], which reads templates and
+//! Markdown from a source directory, renders them with the variables from a TOML
+//! configuration file, and writes the result into an output directory.
+
+use std::{collections::{BTreeMap, HashSet}, path::{Path, PathBuf}, io::Write, time::{Duration, Instant}};
+use color_eyre::eyre::{Result, bail};
+use handlebars::no_escape;
+use rayon::prelude::*;
+use tracing::{event, Level, span};
+use cache::{Cache, Dependencies};
+use content::Page;
+
+pub mod cache;
+pub mod collections;
+pub mod content;
+pub mod data;
+pub mod feeds;
+pub mod helpers;
+pub mod highlight;
+pub mod links;
+pub mod pagination;
+pub mod records;
+pub mod serve;
+pub mod shortcodes;
+pub mod sitemap;
+pub mod taxonomies;
+pub mod watch;
+
+/// Layout used for Markdown files without a template of their own, unless the
+/// page or the configuration sets `layout`.
+pub const DEFAULT_LAYOUT: &str = "_layout";
+
+/// Directory within the source directory whose templates are partials.
+pub const PARTIALS_DIR: &str = "_partials";
+
+/// Directory within the source directory whose templates are shortcodes,
+/// which Markdown content can invoke.
+pub const SHORTCODES_DIR: &str = "_shortcodes";
+
+/// Variables made available to every template.
+pub type Context = toml::Table;
+
+/// Deep-merge `overlay` into `base`. Tables are merged key by key, any other
+/// value in `overlay` replaces the one in `base`.
+pub fn merge(base: &mut Context, overlay: Context) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(inner)), toml::Value::Table(value)) => merge(inner, value),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Parse a `key.path=value` override of the configuration into a table
+/// holding just that value. The value is read as TOML, falling back to a
+/// string, so `draft=true` sets a boolean and `base_url=https://x.org` a string.
+pub fn parse_override(setting: &str) -> Result<Context> {
+    let (key, value) = match setting.split_once('=') {
+        Some((key, value)) => (key.trim(), value.trim()),
+        None => bail!("Expected `key.path=value`, got `{setting}`."),
+    };
+    let mut table = match toml::from_str::<Context>(&format!("{key} = {value}")) {
+        Ok(table) => table,
+        Err(_) => match toml::from_str(&format!("{key} = {}", toml::Value::String(value.to_owned()))) {
+            Ok(table) => table,
+            Err(_) => bail!("`{key}` is not a valid key path."),
+        },
+    };
+    content::stringify_datetimes(&mut table);
+    Ok(table)
+}
+
+/// The configuration overlay of the environment `env` for the configuration
+/// file `config`: `tinytemple.production.toml` for `tinytemple.toml`.
+pub fn config_overlay(config: &Path, env: &str) -> PathBuf {
+    let stem = config.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    match config.extension() {
+        Some(extension) => config.with_file_name(format!("{stem}.{env}.{}", extension.to_string_lossy())),
+        None => config.with_file_name(format!("{stem}.{env}")),
+    }
+}
+
+/// The site-relative URL of the page rendered from the source named `name`.
+pub fn url_for(name: &str) -> String {
+    format!("/{name}.html")
+}
+
+/// Turn `text` into a lowercase slug suitable for URLs, with runs of anything
+/// other than letters and digits collapsed into single dashes.
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_owned()
+}
+
+/// Escape text for use in HTML and XML content and attribute values.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Decode the `%XX` escapes of a URL path or fragment. Invalid escapes are
+/// kept as they are.
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| text.get(i + 1..i + 3))
+            .flatten()
+            .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            },
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// The absolute URL of `url`, if `base_url` is configured.
+pub fn permalink(ctx: &Context, url: &str) -> Option<String> {
+    let base_url = ctx.get("base_url")?.as_str()?;
+    Some(format!("{}/{}", base_url.trim_end_matches('/'), url.trim_start_matches('/')))
+}
+
+/// The inputs of a single site build.
+#[derive(Debug, Clone)]
+pub struct Site {
+    /// Source directory for template files and content files.
+    pub sourcedir: PathBuf,
+
+    /// Source directory for files which will be copied verbatim into the output.
+    pub staticdir: PathBuf,
+
+    /// Directory of Rhai scripts registered as Handlebars helpers, each
+    /// under its file name.
+    pub helperdir: PathBuf,
+
+    /// Directory of JSON, YAML, TOML and CSV files exposed to templates as
+    /// `data`.
+    pub datadir: PathBuf,
+
+    /// Output directory for rendered HTML.
+    pub outdir: PathBuf,
+
+    /// TOML Configuration file.
+    pub config: PathBuf,
+
+    /// Environment whose configuration overlay, like `tinytemple.production.toml`
+    /// for `production`, is merged over the configuration file.
+    pub env: Option<String>,
+
+    /// Values deep-merged over the configuration, and its overlay, last.
+    pub overrides: Context,
+
+    /// Publish pages with `draft = true`.
+    pub drafts: bool,
+
+    /// Publish pages dated in the future.
+    pub future: bool,
+
+    /// Replace pages that fail to render with a page describing the error,
+    /// rather than leaving them out. Used when previewing a site.
+    pub error_pages: bool,
+
+    /// Cache file for incremental builds. Without one, every build starts
+    /// from an empty output directory and renders everything.
+    pub cache: Option<PathBuf>,
+
+    /// Number of threads rendering pages at once. `0` uses one per CPU core.
+    pub jobs: usize,
+}
+
+impl Default for Site {
+    fn default() -> Self {
+        Self {
+            sourcedir: PathBuf::from("./content/"),
+            staticdir: PathBuf::from("./static/"),
+            helperdir: PathBuf::from("./helpers/"),
+            datadir: PathBuf::from("./data/"),
+            outdir: PathBuf::from("./html/"),
+            config: PathBuf::from("./tinytemple.toml"),
+            env: None,
+            overrides: Context::new(),
+            drafts: false,
+            future: false,
+            error_pages: false,
+            cache: None,
+            jobs: 0,
+        }
+    }
+}
+
+/// A template which could not be rendered or written to the output directory.
+#[derive(Debug, Clone, Default)]
+pub struct RenderFailure {
+    /// Name of the template that failed.
+    pub template: String,
+
+    /// The file the template should have been written to.
+    pub outfile: PathBuf,
+
+    /// Human readable description of what went wrong.
+    pub error: String,
+
+    /// Line of the template the error was found on, if known.
+    pub line: Option<usize>,
+
+    /// Column of the template the error was found on, if known.
+    pub column: Option<usize>,
+}
+
+/// An HTML page written by a build.
+#[derive(Debug, Clone)]
+pub struct RenderedPage {
+    /// Site-relative URL of the page.
+    pub url: String,
+
+    /// The file the page was written to.
+    pub outfile: PathBuf,
+
+    /// When the page's source last changed, as an RFC 3339 timestamp.
+    pub lastmod: Option<String>,
+}
+
+/// Summary of a finished build.
+#[derive(Debug, Clone, Default)]
+pub struct BuildReport {
+    /// Every file that was rendered into the output directory.
+    pub rendered: Vec<PathBuf>,
+
+    /// Files left as the previous build wrote them, since none of their
+    /// inputs changed.
+    pub unchanged: Vec<PathBuf>,
+
+    /// The HTML pages among the rendered and unchanged files.
+    pub pages: Vec<RenderedPage>,
+
+    /// Templates that were skipped because they failed to render.
+    pub failed: Vec<RenderFailure>,
+
+    /// Wall-clock time taken by the build.
+    pub elapsed: Duration,
+}
+
+impl Site {
+    /// Create a site using the default directory layout.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the configuration file into a fresh [`Context`], with the overlay
+    /// of the environment, if there is one, and then the overrides merged over
+    /// it.
+    pub fn load_config(&self) -> Result<Context> {
+        let mut cfg = self.read_config(&self.config)?;
+        if let Some(env) = &self.env {
+            let overlay = config_overlay(&self.config, env);
+            if overlay.is_file() {
+                merge(&mut cfg, self.read_config(&overlay)?);
+            } else {
+                let infile = overlay.as_os_str().to_string_lossy();
+                event!(Level::WARN, path = %infile, env = %env, "No config file for this environment.");
+            }
+            cfg.entry("env").or_insert_with(|| toml::Value::String(env.clone()));
+        }
+        merge(&mut cfg, self.overrides.clone());
+        Ok(cfg)
+    }
+
+    fn read_config(&self, path: &Path) -> Result<Context> {
+        match std::fs::read_to_string(path) {
+            Ok(raw) => match toml::from_str(&raw) {
+                Ok(mut cfg) => {
+                    content::stringify_datetimes(&mut cfg);
+                    Ok(cfg)
+                },
+                Err(e) => {
+                    let infile = path.as_os_str().to_string_lossy();
+                    event!(Level::ERROR, path = %infile, error = %e, "Unable to parse config file.");
+                    bail!("A fatal error has occurred.");
+                }
+            },
+            Err(e) => {
+                let infile = path.as_os_str().to_string_lossy();
+                event!(Level::ERROR, path = %infile, error = %e, "Unable to read config file.");
+                bail!("A fatal error has occurred.");
+            }
+        }
+    }
+
+    /// Render the whole site into the output directory.
+    ///
+    /// Fatal problems (unreadable config, missing directories) are returned as
+    /// errors. Templates that fail individually are logged, skipped, and listed
+    /// in the returned [`BuildReport`].
+    pub fn build(&self) -> Result<BuildReport> {
+        let now = Instant::now();
+
+        let mut ctx = self.load_config()?;
+        match data::load(&self.datadir) {
+            Ok(data) if data.is_empty() => (),
+            Ok(data) => merge(&mut ctx, Context::from_iter([("data".to_owned(), toml::Value::Table(data))])),
+            Err(e) => {
+                let dd = self.datadir.as_os_str().to_string_lossy();
+                event!(Level::ERROR, path = %dd, error = %e, "Unable to load data files.");
+                bail!("A fatal error has occurred.");
+            }
+        }
+
+        match std::fs::read_dir(&self.sourcedir) {
+            Ok(_) => (),
+            Err(e) => {
+                let id = self.sourcedir.as_os_str().to_string_lossy();
+                event!(Level::ERROR, path = %id, error = %e, "Unable to read input directory.");
+                bail!("A fatal error has occurred.");
+            }
+        };
+
+        // First wipe out the old output directory, unless the cache knows
+        // what is in it.
+        let previous = self.cache.as_ref().and_then(|path| Cache::load(path, &self.outdir));
+        if let (Some(path), Some(_)) = (&self.cache, &previous) {
+            // Should the build fail part way, the next one starts from scratch.
+            let _ = std::fs::remove_file(path);
+        }
+        if previous.is_none() {
+            match std::fs::remove_dir_all(&self.outdir) {
+                Ok(_) => (),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                Err(e) => {
+                    let od = self.outdir.as_os_str().to_string_lossy();
+                    event!(Level::ERROR, path = %od, error = %e, "Unable to clear output directory.");
+                    bail!("A fatal error has occurred.");
+                }
+            };
+        }
+        // Recreate it for use.
+        match std::fs::create_dir_all(&self.outdir) {
+            Ok(_) => (),
+            Err(e) => {
+                let od = self.outdir.as_os_str().to_string_lossy();
+                event!(Level::ERROR, path = %od, error = %e, "Unable to create output directory.");
+                bail!("A fatal error has occurred.");
+            }
+        };
+
+        let mut progress = Progress::default();
+        let pool = match rayon::ThreadPoolBuilder::new().num_threads(self.jobs).build() {
+            Ok(pool) => pool,
+            Err(e) => {
+                event!(Level::ERROR, error = %e, "Unable to start worker threads.");
+                bail!("A fatal error has occurred.");
+            }
+        };
+
+        // Now read all the source files, apply the context, render, and output.
+        let mut engine = handlebars::Handlebars::new();
+        engine.register_escape_fn(no_escape);
+        helpers::register(&mut engine);
+
+        // Scripts in the helper directory become helpers, so that
+        // `helpers/money.rhai` is used as `{{money price}}`.
+        let mut helpers = Vec::new();
+        for (name, path) in source_files(&self.helperdir, ".rhai") {
+            let script = match std::fs::read_to_string(&path) {
+                Ok(script) => script,
+                Err(e) => {
+                    let infile = path.as_os_str().to_string_lossy();
+                    event!(Level::ERROR, path = %infile, error = %e, "Unable to read helper script.");
+                    bail!("A fatal error has occurred.");
+                }
+            };
+            if let Err(e) = engine.register_script_helper(&name, &script) {
+                let infile = path.as_os_str().to_string_lossy();
+                event!(Level::ERROR, path = %infile, error = %e, "Unable to compile helper script.");
+                bail!("A fatal error has occurred.");
+            }
+            helpers.push(name);
+            helpers.push(script);
+        }
+        // Any template may call any helper.
+        let helpers = cache::hash(&helpers);
+
+        let mut pages = Vec::new();
+        let mut templates = BTreeMap::new();
+        for (name, path) in source_files(&self.sourcedir, ".hbs") {
+            // Partials are registered under their name within the partials
+            // directory, so `_partials/header.hbs` is used as `{{> header}}`.
+            let name = match name.strip_prefix(PARTIALS_DIR).and_then(|n| n.strip_prefix('/')) {
+                Some(partial) => partial.to_owned(),
+                None => name,
+            };
+
+            // Templates may carry front matter of their own, just like
+            // Markdown files.
+            let raw = match std::fs::read_to_string(&path) {
+                Ok(raw) => raw,
+                Err(e) => {
+                    let infile = path.as_os_str().to_string_lossy();
+                    event!(Level::ERROR, path = %infile, error = %e, "Unable to read input template.");
+                    bail!("A fatal error has occurred.");
+                }
+            };
+            let (front_matter, body) = match content::split_front_matter(&raw) {
+                Ok(split) => split,
+                Err(e) => {
+                    let infile = path.as_os_str().to_string_lossy();
+                    event!(Level::ERROR, path = %infile, error = %e, "Unable to parse front matter.");
+                    bail!("A fatal error has occurred.");
+                }
+            };
+
+            match engine.register_template_string(&name, body) {
+                Ok(_) => (),
+                Err(e) if self.error_pages => {
+                    // Keep going, so that the broken page can show the error.
+                    let infile = path.as_os_str().to_string_lossy();
+                    event!(Level::ERROR, path = %infile, error = %e, "Unable to parse input template.");
+                    let failure = RenderFailure {
+                        template: name.clone(),
+                        outfile: self.outdir.join(format!("{name}.html")),
+                        error: e.to_string(),
+                        line: e.line_no,
+                        column: e.column_no,
+                    };
+                    if is_hidden(&path, &self.sourcedir) {
+                        progress.report.failed.push(failure);
+                    } else {
+                        self.fail_page(failure, &mut progress);
+                    }
+                    continue;
+                },
+                Err(e) => {
+                    let infile = path.as_os_str().to_string_lossy();
+                    event!(Level::ERROR, path = %infile, error = %e, "Unable to parse input template.");
+                    bail!("A fatal error has occurred.");
+                }
+            };
+            let hash = cache::hash(&[&raw]);
+            templates.insert(name.clone(), Template { path: path.clone(), front_matter, hash });
+
+            if !is_hidden(&path, &self.sourcedir) {
+                pages.push(name);
+            }
+        }
+
+        // Work out what every template's output depends on.
+        let sources = templates.iter()
+            .map(|(name, template)| (name.clone(), template.hash.clone()))
+            .collect();
+        let dependencies = templates.keys()
+            .map(|name| {
+                let deps = Dependencies { helpers: helpers.clone(), ..Dependencies::of(&engine, name, &sources) };
+                (name.clone(), deps)
+            })
+            .collect();
+        let build = Build {
+            engine,
+            previous: previous.unwrap_or_else(|| Cache::new(&self.outdir)),
+            dependencies,
+        };
+
+        // Fenced code blocks are highlighted if the config asks for it.
+        let highlighter = match highlight::Highlighter::from_context(&ctx) {
+            Some(Ok(highlighter)) => Some(highlighter),
+            Some(Err(e)) => {
+                event!(Level::ERROR, error = %e, "Invalid highlighting configuration.");
+                None
+            },
+            None => None,
+        };
+        let anchors = ctx.get("markdown")
+            .and_then(|markdown| markdown.get("anchors"))
+            .and_then(|anchors| anchors.as_bool())
+            .unwrap_or(false);
+        let markdown = content::Markdown {
+            highlighter: highlighter.as_ref(),
+            anchors,
+            shortcodes: Some(shortcodes::Shortcodes::new(&build.engine, &ctx)),
+            page: None,
+        };
+
+        // Load every Markdown file up front, so that templates can list them.
+        let content_files: Vec<(String, PathBuf)> = source_files(&self.sourcedir, ".md")
+            .into_iter()
+            .filter(|(_, content_file)| !is_hidden(content_file, &self.sourcedir))
+            .collect();
+        let loaded: Vec<Result<Page>> = pool.install(|| {
+            content_files.par_iter()
+                .map(|(name, content_file)| Page::load(name, content_file, &markdown))
+                .collect()
+        });
+        let mut contents = Vec::new();
+        let mut broken = HashSet::new();
+        for ((name, content_file), page) in content_files.iter().zip(loaded) {
+            match page {
+                Ok(page) => contents.push(page),
+                Err(e) => {
+                    // The page is left out of the build, along with any
+                    // template of the same name which would have shown it.
+                    let infile = content_file.as_os_str().to_string_lossy();
+                    event!(Level::ERROR, path = %infile, error = %e, "Unable to load content file.");
+                    let failure = RenderFailure {
+                        template: name.clone(),
+                        outfile: self.outdir.join(format!("{name}.html")),
+                        error: e.to_string(),
+                        ..Default::default()
+                    };
+                    self.fail_page(failure, &mut progress);
+                    broken.insert(name.clone());
+                }
+            }
+        }
+
+        // Drafts, future and expired pages are left out of everything, unless
+        // the build or the config asks for them.
+        let enabled = |key: &str| matches!(ctx.get(key), Some(toml::Value::Boolean(true)));
+        let publishing = content::Publishing {
+            drafts: self.drafts || enabled("drafts"),
+            future: self.future || enabled("future"),
+            now: chrono::Utc::now().fixed_offset(),
+        };
+        let (contents, unpublished): (Vec<Page>, Vec<Page>) = contents.into_iter()
+            .partition(|page| publishing.includes(&page.front_matter));
+        let unpublished: HashSet<String> = unpublished.into_iter()
+            .map(|page| {
+                let infile = page.path.as_os_str().to_string_lossy();
+                event!(Level::DEBUG, path = %infile, "Leaving out unpublished page.");
+                page.name
+            })
+            .collect();
+
+        let collections = collections::collect(&contents);
+        let mut listing = collections::to_context(&collections, &ctx);
+
+        // A collection whose pages are all left out is still listed, empty, so
+        // that templates paginating it render a single empty page.
+        for (name, _) in &content_files {
+            if let Some((collection, _)) = name.split_once('/') {
+                listing.entry(collection.to_owned()).or_insert_with(|| toml::Value::Array(Vec::new()));
+            }
+        }
+        ctx.insert("collections".to_owned(), toml::Value::Table(listing));
+
+        let taxonomies: Vec<(String, Vec<taxonomies::Term>)> = taxonomies::configured(&ctx)
+            .into_iter()
+            .map(|taxonomy| {
+                let terms = taxonomies::collect(&contents, &taxonomy);
+                (taxonomy, terms)
+            })
+            .collect();
+        let terms = taxonomies.iter()
+            .map(|(taxonomy, terms)| (taxonomy.clone(), toml::Value::Table(taxonomies::to_context(terms, &ctx))))
+            .collect();
+        ctx.insert("taxonomies".to_owned(), toml::Value::Table(terms));
+
+        // Next work out every page to render, starting with the templates.
+        let mut jobs = Vec::new();
+        for name in &pages {
+            let published = templates.get(name).is_none_or(|template| publishing.includes(&template.front_matter));
+            if !published || unpublished.contains(name) || broken.contains(name) {
+                continue;
+            }
+
+            // Layer the template's front matter and then the sibling Markdown
+            // file, if there is any, over the global context for this page only.
+            let sibling = contents.iter().find(|page| &page.name == name);
+            let mut layers = vec![template_layer(templates.get(name))];
+            match sibling {
+                Some(page) => layers.push(page_layer(page)),
+                None => layers.push(layer([("url", toml::Value::String(url_for(name)))])),
+            }
+            let job = Job { template: name.clone(), name: name.clone(), content: sibling, layers };
+
+            // Templates with records render one output per record instead.
+            if let Some(toml::Value::String(source)) = job.own("records") {
+                match job.records(&ctx, source) {
+                    Ok(records) => jobs.extend(records),
+                    Err(e) => {
+                        event!(Level::ERROR, template = %name, error = %e, "Unable to render records.");
+                        self.fail_page(RenderFailure {
+                            template: name.clone(),
+                            outfile: self.outdir.join(format!("{name}.html")),
+                            error: e,
+                            ..Default::default()
+                        }, &mut progress);
+                    }
+                }
+                continue;
+            }
+
+            // Paginated templates render one output per page of their listing.
+            let collection = match job.own("paginate") {
+                Some(toml::Value::String(collection)) => collection.clone(),
+                _ => {
+                    jobs.push(job);
+                    continue;
+                }
+            };
+            match ctx["collections"].get(&collection) {
+                Some(toml::Value::Array(entries)) => jobs.extend(job.paginate(&ctx, entries)),
+                _ => {
+                    event!(Level::ERROR, template = %name, collection = %collection, "Paginated collection does not exist.");
+                    self.fail_page(RenderFailure {
+                        template: name.clone(),
+                        outfile: self.outdir.join(format!("{name}.html")),
+                        error: format!("Paginated collection `{collection}` does not exist."),
+                        ..Default::default()
+                    }, &mut progress);
+                }
+            }
+        }
+
+        // Then every Markdown file without a template of its own, which is
+        // rendered through its layout.
+        for page in &contents {
+            if pages.contains(&page.name) {
+                continue;
+            }
+
+            let mut job = Job {
+                template: DEFAULT_LAYOUT.to_owned(),
+                name: page.name.clone(),
+                content: Some(page),
+                layers: vec![page_layer(page)],
+            };
+            if let Some(toml::Value::String(layout)) = job.get(&ctx, "layout") {
+                job.template = layout.clone();
+            }
+            if !templates.contains_key(&job.template) {
+                let infile = page.path.as_os_str().to_string_lossy();
+                event!(Level::ERROR, path = %infile, layout = %job.template, "Layout template does not exist.");
+                self.fail_page(RenderFailure {
+                    template: job.template.clone(),
+                    outfile: self.outdir.join(format!("{}.html", page.name)),
+                    error: format!("Layout template `{}` does not exist.", job.template),
+                    ..Default::default()
+                }, &mut progress);
+                continue;
+            }
+
+            jobs.push(job);
+        }
+
+        // Then the pages of every taxonomy.
+        for (taxonomy, terms) in &taxonomies {
+            jobs.extend(taxonomy_jobs(taxonomy, terms, &ctx, &templates));
+        }
+
+        // Every page is written to the file its name says, so two pages with
+        // the same name would overwrite each other in whatever order they
+        // happened to finish. Since there is no telling which is meant, all of
+        // them are left out.
+        let mut names = HashSet::new();
+        let clashes: HashSet<String> = jobs.iter()
+            .filter(|job| !names.insert(&job.name))
+            .map(|job| job.name.clone())
+            .collect();
+        let (clashing, kept): (Vec<Job>, Vec<Job>) = jobs.into_iter()
+            .partition(|job| clashes.contains(&job.name));
+        let jobs = kept;
+        for job in clashing {
+            let url = url_for(&job.name);
+            event!(Level::ERROR, template = %job.template, url = %url, "More than one page is written to the same file.");
+            self.fail_page(RenderFailure {
+                template: job.template.clone(),
+                outfile: self.outdir.join(format!("{}.html", job.name)),
+                error: format!("More than one page is written to `{url}`."),
+                ..Default::default()
+            }, &mut progress);
+        }
+
+        // Render them all in parallel. Every page writes its own file, and
+        // what each wrote is gathered in order, so the build comes out the
+        // same no matter how the pages were spread over threads.
+        let rendered: Vec<Progress> = pool.install(|| {
+            jobs.par_iter()
+                .map(|job| {
+                    let mut part = Progress::default();
+                    self.render_page(&build, job, &ctx, &mut part);
+                    part
+                })
+                .collect()
+        });
+        rendered.into_iter().for_each(|part| progress.extend(part));
+
+        // Then write the stylesheet for highlighted code, if it uses one.
+        if let Some(highlighter) = &highlighter {
+            if let Some(css) = highlighter.css() {
+                self.write_file(&build, "highlight", &highlighter.stylesheet, &css, &mut progress);
+            }
+        }
+
+        // Then write the feeds of every collection and taxonomy term.
+        if let Some(feed_config) = feeds::FeedConfig::from_context(&ctx) {
+            let _span = span!(Level::INFO, "render_feeds").entered();
+
+            if ctx.contains_key("base_url") {
+                let site_title = ctx.get("title").and_then(|t| t.as_str());
+                let feed_title = |name: &str| match site_title {
+                    Some(title) => format!("{title}: {name}"),
+                    None => name.to_owned(),
+                };
+
+                let mut feeds = Vec::new();
+                for (collection, entries) in &collections {
+                    let index = format!("{collection}/index");
+                    feeds.push(feeds::Feed {
+                        title: feed_title(collection),
+                        link: if pages.contains(&index) { url_for(&index) } else { "/".to_owned() },
+                        dir: collection.clone(),
+                        pages: entries.clone(),
+                    });
+                }
+
+                for (taxonomy, terms) in &taxonomies {
+                    for term in terms {
+                        let name = term.output_name(taxonomy);
+                        feeds.push(feeds::Feed {
+                            title: feed_title(&term.name),
+                            link: url_for(&name),
+                            dir: format!("{taxonomy}/{}", term.slug),
+                            pages: term.pages.clone(),
+                        });
+                    }
+                }
+
+                let written: Vec<Progress> = pool.install(|| {
+                    feeds.par_iter()
+                        .map(|feed| {
+                            let mut part = Progress::default();
+                            self.write_feed(&build, feed, &feed_config, &ctx, &mut part);
+                            part
+                        })
+                        .collect()
+                });
+                written.into_iter().for_each(|part| progress.extend(part));
+            } else {
+                event!(Level::ERROR, "Feeds require `base_url` to be set in the config file.");
+            }
+
+            let _ = _span.exit();
+        }
+
+        // Then list every page in the sitemap, which needs absolute URLs.
+        if ctx.contains_key("base_url") {
+            match sitemap::SitemapConfig::from_context(&ctx) {
+                Some(Ok(sitemap_config)) => {
+                    let _span = span!(Level::INFO, "render_sitemap").entered();
+
+                    let pages = progress.report.pages.clone();
+                    for (name, xml) in sitemap::render(&pages, &sitemap_config, &ctx) {
+                        self.write_file(&build, "sitemap", &name, &xml, &mut progress);
+                    }
+
+                    // A robots.txt among the static files takes precedence.
+                    if sitemap_config.robots && !self.staticdir.join("robots.txt").exists() {
+                        let robots = sitemap::robots(&ctx);
+                        self.write_file(&build, "robots", "robots.txt", &robots, &mut progress);
+                    }
+
+                    let _ = _span.exit();
+                },
+                Some(Err(e)) => {
+                    event!(Level::ERROR, error = %e, "Invalid sitemap exclusion pattern.");
+                },
+                None => (),
+            }
+        } else if ctx.contains_key("sitemap") {
+            event!(Level::WARN, "The sitemap requires `base_url` to be set in the config file.");
+        }
+
+        // Then copy the static directory's contents into the output directory.
+        self.copy_static(&build, &mut progress)?;
+
+        // Last, clear out whatever the previous build wrote that this one did
+        // not, and remember what this one wrote for the next.
+        self.remove_stale(&build, &progress);
+        if let Some(path) = &self.cache {
+            let cache = Cache { outputs: progress.outputs, ..Cache::new(&self.outdir) };
+            if let Err(e) = cache.save(path) {
+                let cache = path.as_os_str().to_string_lossy();
+                event!(Level::ERROR, path = %cache, error = %e, "Unable to write cache file.");
+            }
+        }
+
+        let mut report = progress.report;
+        report.elapsed = now.elapsed();
+        Ok(report)
+    }
+
+    /// Render `job` into `{name}.html` in the output directory, unless nothing
+    /// it depends on changed since the previous build.
+    fn render_page(&self, build: &Build, job: &Job, ctx: &Context, progress: &mut Progress) {
+        let _span = span!(Level::INFO, "render_page", template = %job.template, page = %job.name).entered();
+
+        let ctx = job.context(ctx);
+        let output = format!("{}.html", job.name);
+        let outfile = self.outdir.join(&output);
+        let page = RenderedPage {
+            url: ctx.get("url").and_then(|u| u.as_str()).map(|u| u.to_owned()).unwrap_or_else(|| url_for(&job.name)),
+            outfile: outfile.clone(),
+            lastmod: ctx.get("lastmod").and_then(|l| l.as_str()).map(|l| l.to_owned()),
+        };
+
+        let deps = build.dependencies.get(&job.template).cloned().unwrap_or_default();
+        let hash = deps.hash(&output, &ctx);
+        let record = deps.output(hash, job.content.map(|page| format!("{}.md", page.name)));
+        if build.previous.is_current(&output, &record.hash) {
+            progress.report.unchanged.push(outfile);
+            progress.report.pages.push(page);
+            progress.outputs.insert(output, record);
+            let _ = _span.exit();
+            return;
+        }
+
+        match build.engine.render(&job.template, &ctx) {
+            Ok(rendered) => {
+                if self.write_output(&job.template, outfile.clone(), &rendered, &mut progress.report) {
+                    progress.report.pages.push(page);
+                    progress.outputs.insert(output, record);
+                }
+            },
+            Err(e) => {
+                event!(Level::ERROR, template = %job.template, error = %e, "Error rendering template.");
+                let failure = RenderFailure {
+                    template: e.template_name.clone().unwrap_or_else(|| job.template.clone()),
+                    outfile,
+                    error: e.desc.clone(),
+                    line: e.line_no,
+                    column: e.column_no,
+                };
+                self.fail_page(failure, progress);
+            }
+        }
+
+        let _ = _span.exit();
+    }
+
+    /// Record a page which could not be rendered. When building error pages,
+    /// its output is replaced by a page describing the failure.
+    fn fail_page(&self, failure: RenderFailure, progress: &mut Progress) {
+        if self.error_pages {
+            let page = serve::error_page(&failure);
+            if self.write_output(&failure.template, failure.outfile.clone(), &page, &mut progress.report) {
+                // Tracked without a hash, so that it is replaced by the next
+                // build no matter what.
+                if let Some(name) = self.output_name(&failure.outfile) {
+                    progress.outputs.insert(name, cache::Output::default());
+                }
+            }
+        }
+        progress.report.failed.push(failure);
+    }
+
+    /// Write generated `contents` to the output file `name`, unless the
+    /// previous build already wrote exactly that.
+    fn write_file(&self, build: &Build, source: &str, name: &str, contents: &str, progress: &mut Progress) {
+        let outfile = self.outdir.join(name);
+        let record = cache::Output { hash: cache::hash(&[contents]), ..Default::default() };
+        if build.previous.is_current(name, &record.hash) {
+            progress.report.unchanged.push(outfile);
+            progress.outputs.insert(name.to_owned(), record);
+        } else if self.write_output(source, outfile, contents, &mut progress.report) {
+            progress.outputs.insert(name.to_owned(), record);
+        }
+    }
+
+    /// Write `contents` to `outfile`, creating its parent directories.
+    /// `source` names what produced the file in case of failure. Returns
+    /// whether the file was written.
+    fn write_output(&self, source: &str, outfile: PathBuf, contents: &str, report: &mut BuildReport) -> bool {
+        let parentdir = match outfile.parent() {
+            Some(p) => p,
+            None => {
+                let dir = outfile.as_os_str().to_string_lossy();
+                event!(Level::ERROR, path = %dir, "Error manipulating output directory.");
+                report.failed.push(RenderFailure {
+                    template: source.to_owned(),
+                    outfile,
+                    error: "Error manipulating output directory.".to_owned(),
+                    ..Default::default()
+                });
+                return false;
+            }
+        };
+
+        match std::fs::create_dir_all(parentdir) {
+            Ok(_) => (),
+            Err(e) => {
+                let dir = parentdir.as_os_str().to_string_lossy();
+                event!(Level::ERROR, path = %dir, error = %e, "Unable to create output subdirectory.");
+                report.failed.push(RenderFailure {
+                    template: source.to_owned(),
+                    outfile,
+                    error: e.to_string(),
+                    ..Default::default()
+                });
+                return false;
+            }
+        };
+
+        match std::fs::File::create(&outfile) {
+            Ok(mut fd) => match write!(fd, "{contents}") {
+                Ok(_) => {
+                    report.rendered.push(outfile);
+                    true
+                },
+                Err(e) => {
+                    let path = outfile.as_os_str().to_string_lossy();
+                    event!(Level::ERROR, path = %path, error = %e, "Error writing to output file.");
+                    report.failed.push(RenderFailure { template: source.to_owned(), outfile, error: e.to_string(), ..Default::default() });
+                    false
+                }
+            },
+            Err(e) => {
+                let path = outfile.as_os_str().to_string_lossy();
+                event!(Level::ERROR, path = %path, error = %e, "Error creating output file.");
+                report.failed.push(RenderFailure { template: source.to_owned(), outfile, error: e.to_string(), ..Default::default() });
+                false
+            }
+        }
+    }
+
+    /// Write every configured format of `feed`.
+    fn write_feed(&self, build: &Build, feed: &feeds::Feed, config: &feeds::FeedConfig, ctx: &Context, progress: &mut Progress) {
+        for format in &config.formats {
+            let name = feed.output_name(*format);
+            let xml = feed.render(*format, config, ctx);
+            self.write_file(build, &name, &name, &xml, progress);
+        }
+    }
+
+    /// Copy the static directory's contents into the output directory,
+    /// skipping files which have not changed since the previous build. Static
+    /// files may not overwrite anything the build rendered.
+    fn copy_static(&self, build: &Build, progress: &mut Progress) -> Result<()> {
+        for entry in walkdir::WalkDir::new(&self.staticdir).min_depth(1) {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    event!(Level::ERROR, error = %e, "Unable to copy static files to output.");
+                    bail!("A fatal error has occurred.");
+                }
+            };
+            let relative = match entry.path().strip_prefix(&self.staticdir) {
+                Ok(relative) => relative,
+                Err(_) => continue,
+            };
+            let outfile = self.outdir.join(relative);
+
+            if entry.file_type().is_dir() {
+                if let Err(e) = std::fs::create_dir_all(&outfile) {
+                    let dir = outfile.as_os_str().to_string_lossy();
+                    event!(Level::ERROR, path = %dir, error = %e, "Unable to create output subdirectory.");
+                    bail!("A fatal error has occurred.");
+                }
+                continue;
+            }
+
+            let name = match self.output_name(&outfile) {
+                Some(name) => name,
+                None => continue,
+            };
+            if progress.outputs.contains_key(&name) {
+                event!(Level::ERROR, path = %name, "Static file conflicts with a rendered file.");
+                bail!("A fatal error has occurred.");
+            }
+
+            // Static files are too many and too large to hash, so their size
+            // and modification time stand in for their contents.
+            let stamp = match entry.metadata() {
+                Ok(metadata) => {
+                    let modified = metadata.modified().ok()
+                        .and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok())
+                        .map(|m| m.as_nanos())
+                        .unwrap_or_default();
+                    cache::hash(&[metadata.len().to_string(), modified.to_string()])
+                },
+                Err(_) => String::new(),
+            };
+            if !build.previous.is_current(&name, &stamp) {
+                if let Err(e) = std::fs::copy(entry.path(), &outfile) {
+                    let infile = entry.path().as_os_str().to_string_lossy();
+                    event!(Level::ERROR, path = %infile, error = %e, "Unable to copy static files to output.");
+                    bail!("A fatal error has occurred.");
+                }
+            }
+            progress.outputs.insert(name, cache::Output { hash: stamp, ..Default::default() });
+        }
+        Ok(())
+    }
+
+    /// Remove the files the previous build wrote which this build did not,
+    /// along with any directories left empty.
+    fn remove_stale(&self, build: &Build, progress: &Progress) {
+        for name in build.previous.outputs.keys().filter(|name| !progress.outputs.contains_key(*name)) {
+            let outfile = self.outdir.join(name);
+            match std::fs::remove_file(&outfile) {
+                Ok(_) => (),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                Err(e) => {
+                    let path = outfile.as_os_str().to_string_lossy();
+                    event!(Level::WARN, path = %path, error = %e, "Unable to remove stale output file.");
+                }
+            }
+
+            let mut dir = outfile.parent();
+            while let Some(parent) = dir {
+                if parent == self.outdir || std::fs::remove_dir(parent).is_err() {
+                    break;
+                }
+                dir = parent.parent();
+            }
+        }
+    }
+
+    /// Name of `outfile` relative to the output directory, with `/` separators.
+    fn output_name(&self, outfile: &Path) -> Option<String> {
+        let relative = outfile.strip_prefix(&self.outdir).ok()?
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        Some(relative)
+    }
+}
+
+/// What every page of a build is rendered with, shared by the threads
+/// rendering them.
+struct Build {
+    engine: handlebars::Handlebars<'static>,
+
+    /// What the previous build left in the output directory. Empty when
+    /// there is no cache to trust, in which case the directory was cleared.
+    previous: Cache,
+
+    /// What the output of every template depends on, by template name.
+    dependencies: BTreeMap<String, Dependencies>,
+}
+
+/// What a build, or one part of it, has written to the output directory.
+#[derive(Debug, Default)]
+struct Progress {
+    report: BuildReport,
+
+    /// Every file written, for the cache.
+    outputs: BTreeMap<String, cache::Output>,
+}
+
+impl Progress {
+    /// Add what another part of the build wrote after what this one did.
+    fn extend(&mut self, other: Progress) {
+        self.report.rendered.extend(other.report.rendered);
+        self.report.unchanged.extend(other.report.unchanged);
+        self.report.pages.extend(other.report.pages);
+        self.report.failed.extend(other.report.failed);
+        self.outputs.extend(other.outputs);
+    }
+}
+
+/// A page to render: `template` written to `{name}.html`, with `layers`
+/// merged over the site's context in order.
+#[derive(Debug, Clone)]
+struct Job<'a> {
+    template: String,
+    name: String,
+
+    /// The Markdown file the page comes from, if any.
+    content: Option<&'a Page>,
+
+    layers: Vec<Context>,
+}
+
+impl Job<'_> {
+    /// The context the page is rendered with.
+    fn context(&self, ctx: &Context) -> Context {
+        let mut page_ctx = ctx.clone();
+        for layer in &self.layers {
+            merge(&mut page_ctx, layer.clone());
+        }
+        page_ctx
+    }
+
+    /// Look up the top-level variable `key` in the page's context, without
+    /// building all of it.
+    fn get<'b>(&'b self, ctx: &'b Context, key: &str) -> Option<&'b toml::Value> {
+        self.own(key).or_else(|| ctx.get(key))
+    }
+
+    /// Look up the top-level variable `key` in what the page itself declares,
+    /// leaving out the configuration, for settings which only make sense for
+    /// a single page.
+    fn own(&self, key: &str) -> Option<&toml::Value> {
+        self.layers.iter().rev().find_map(|layer| layer.get(key))
+    }
+
+    /// Split the page into one page per page of `entries`, starting with
+    /// `{name}.html`.
+    fn paginate(&self, ctx: &Context, entries: &[toml::Value]) -> Vec<Self> {
+        let per_page = match self.get(ctx, "per_page") {
+            Some(toml::Value::Integer(n)) if *n > 0 => *n as usize,
+            _ => pagination::DEFAULT_PER_PAGE,
+        };
+
+        pagination::paginate(&self.name, entries, per_page)
+            .into_iter()
+            .map(|pager| {
+                let mut job = self.clone();
+                job.name = pager.name;
+                job.layers.push(layer([
+                    ("url", pager.paginator["url"].clone()),
+                    ("paginator", toml::Value::Table(pager.paginator)),
+                ]));
+                job
+            })
+            .collect()
+    }
+
+    /// Split the page into one page per record of the array at the context
+    /// path `source`, each merged into the context of its own page and
+    /// written to the `permalink` of the page.
+    fn records(&self, ctx: &Context, source: &str) -> Result<Vec<Self>, String> {
+        let (root, path) = source.split_once('.').unwrap_or((source, ""));
+        let entries = match self.get(ctx, root).and_then(|value| records::lookup(value, path)) {
+            Some(toml::Value::Array(entries)) => entries,
+            _ => return Err(format!("Records `{source}` do not exist or are not an array.")),
+        };
+        let permalink = match self.own("permalink") {
+            Some(toml::Value::String(permalink)) => permalink,
+            _ => return Err("Templates with records need a `permalink`.".to_owned()),
+        };
+
+        let mut names = HashSet::new();
+        entries.iter()
+            .map(|entry| {
+                let record = match entry {
+                    toml::Value::Table(record) => record,
+                    _ => return Err(format!("Records `{source}` are not all tables.")),
+                };
+                let name = records::output_name(permalink, record)?;
+                if !names.insert(name.clone()) {
+                    return Err(format!("More than one record is written to `{}`.", url_for(&name)));
+                }
+
+                let mut job = self.clone();
+                let mut layer = record.clone();
+                layer.insert("url".to_owned(), toml::Value::String(url_for(&name)));
+                job.layers.push(layer);
+                job.name = name;
+                Ok(job)
+            })
+            .collect()
+    }
+}
+
+/// A template registered from the source directory.
+#[derive(Debug, Clone)]
+struct Template {
+    path: PathBuf,
+    front_matter: Context,
+
+    /// Hash of the template's source.
+    hash: String,
+}
+
+/// The context a template adds for its pages: its front matter, along with
+/// its `lastmod`.
+fn template_layer(template: Option<&Template>) -> Context {
+    let mut layer = Context::new();
+    if let Some(template) = template {
+        layer = template.front_matter.clone();
+        if let Some(lastmod) = lastmod(&template.front_matter, &template.path) {
+            layer.insert("lastmod".to_owned(), toml::Value::String(lastmod));
+        }
+    }
+    layer
+}
+
+/// The context a Markdown file adds for its page: its front matter, along
+/// with its rendered `content`, its `toc`, its `url` and `lastmod`.
+fn page_layer(page: &Page) -> Context {
+    let mut layer = page.front_matter.clone();
+    layer.insert("content".to_owned(), toml::Value::String(page.content.clone()));
+    let toc = page.toc.iter().map(|heading| toml::Value::Table(heading.to_context())).collect();
+    layer.insert("toc".to_owned(), toml::Value::Array(toc));
+    layer.insert("url".to_owned(), toml::Value::String(page.url()));
+    if let Some(lastmod) = lastmod(&page.front_matter, &page.path) {
+        layer.insert("lastmod".to_owned(), toml::Value::String(lastmod));
+    }
+    layer
+}
+
+/// A context holding just `values`.
+fn layer<const N: usize>(values: [(&str, toml::Value); N]) -> Context {
+    values.into_iter().map(|(key, value)| (key.to_owned(), value)).collect()
+}
+
+/// The term list and term pages of a taxonomy, for whichever of their
+/// templates exist.
+fn taxonomy_jobs<'a>(
+    taxonomy: &str,
+    terms: &[taxonomies::Term],
+    ctx: &Context,
+    templates: &BTreeMap<String, Template>,
+) -> Vec<Job<'a>> {
+    let mut jobs = Vec::new();
+    let taxonomy_layer = layer([("taxonomy", toml::Value::String(taxonomy.to_owned()))]);
+
+    let list = format!("{}/{taxonomy}/list", taxonomies::TAXONOMIES_DIR);
+    if templates.contains_key(&list) {
+        let name = format!("{taxonomy}/index");
+        let terms = terms.iter()
+            .map(|term| toml::Value::Table(term.to_context(taxonomy, ctx, false)))
+            .collect();
+        jobs.push(Job {
+            template: list.clone(),
+            name: name.clone(),
+            content: None,
+            layers: vec![
+                taxonomy_layer.clone(),
+                template_layer(templates.get(&list)),
+                layer([
+                    ("terms", toml::Value::Array(terms)),
+                    ("url", toml::Value::String(url_for(&name))),
+                ]),
+            ],
+        });
+    }
+
+    let single = format!("{}/{taxonomy}/term", taxonomies::TAXONOMIES_DIR);
+    if let Some(template) = templates.get(&single) {
+        // Term pages are paginated when their template sets `per_page`.
+        let paginated = template.front_matter.contains_key("per_page");
+        for term in terms {
+            let name = term.output_name(taxonomy);
+            let mut job = Job {
+                template: single.clone(),
+                name: name.clone(),
+                content: None,
+                layers: vec![
+                    taxonomy_layer.clone(),
+                    template_layer(Some(template)),
+                    layer([("term", toml::Value::Table(term.to_context(taxonomy, ctx, true)))]),
+                ],
+            };
+            if paginated {
+                jobs.extend(job.paginate(ctx, &term.entries(ctx)));
+            } else {
+                job.layers.push(layer([("url", toml::Value::String(url_for(&name)))]));
+                jobs.push(job);
+            }
+        }
+    }
+
+    jobs
+}
+
+/// When a source last changed, as an RFC 3339 timestamp: its `updated` or
+/// `date` front matter value, or else the modification time of its file.
+fn lastmod(front_matter: &Context, path: &Path) -> Option<String> {
+    let declared = front_matter.get("updated")
+        .or_else(|| front_matter.get("date"))
+        .and_then(|d| d.as_str())
+        .and_then(content::parse_date);
+    match declared {
+        Some(date) => Some(date.to_rfc3339()),
+        None => {
+            let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok()?;
+            Some(chrono::DateTime::<chrono::Utc>::from(modified).to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+        }
+    }
+}
+
+/// Whether a source file lives in, or is itself, an `_`-prefixed entry. Such
+/// templates are registered for use as partials and layouts but are never
+/// written to the output directory.
+fn is_hidden(path: &Path, sourcedir: &Path) -> bool {
+    path.strip_prefix(sourcedir)
+        .map(|relative| relative.components().any(|c| c.as_os_str().to_string_lossy().starts_with('_')))
+        .unwrap_or(false)
+}
+
+/// List every file under `dir` ending in `extension`, keyed by its path
+/// relative to `dir` with the extension removed. Names use `/` separators, the
+/// same way handlebars names templates loaded from a directory.
+fn source_files(dir: &Path, extension: &str) -> Vec<(String, PathBuf)> {
+    let mut files: Vec<(String, PathBuf)> = walkdir::WalkDir::new(dir)
+        .min_depth(1)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .filter(|path| {
+            path.file_name()
+                .map(|f| f.to_string_lossy())
+                .map(|f| f.ends_with(extension) && !(f.starts_with('.') || f.starts_with('#')))
+                .unwrap_or(false)
+        })
+        .filter_map(|path| {
+            let relative = path.strip_prefix(dir).ok()?
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let name = relative.strip_suffix(extension)?.to_owned();
+            Some((name, path))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(toml: &str) -> Context {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn merge_is_deep() {
+        let mut base = table("title = 'Site'\n[feed]\nlimit = 20\nformats = ['rss']\n[sitemap]\nrobots = true");
+        merge(&mut base, table("title = 'Other'\n[feed]\nformats = ['atom']\ncontent = 'full'"));
+        assert_eq!(base, table(
+            "title = 'Other'\n[feed]\nlimit = 20\nformats = ['atom']\ncontent = 'full'\n[sitemap]\nrobots = true"
+        ));
+    }
+
+    #[test]
+    fn merge_replaces_tables_with_other_values() {
+        let mut base = table("[feed]\nlimit = 20");
+        merge(&mut base, table("feed = false"));
+        assert_eq!(base, table("feed = false"));
+
+        let mut base = table("feed = false");
+        merge(&mut base, table("[feed]\nlimit = 20"));
+        assert_eq!(base, table("[feed]\nlimit = 20"));
+    }
+
+    #[test]
+    fn parse_override_reads_toml() {
+        assert_eq!(parse_override("drafts=true").unwrap(), table("drafts = true"));
+        assert_eq!(parse_override("feed.limit = 5").unwrap(), table("[feed]\nlimit = 5"));
+        assert_eq!(parse_override("taxonomies=['tags']").unwrap(), table("taxonomies = ['tags']"));
+        assert_eq!(parse_override("date=2024-01-02").unwrap(), table("date = '2024-01-02'"));
+    }
+
+    #[test]
+    fn parse_override_falls_back_to_strings() {
+        assert_eq!(
+            parse_override("base_url=https://example.org").unwrap(),
+            table("base_url = 'https://example.org'"),
+        );
+        assert_eq!(parse_override("title=").unwrap(), table("title = ''"));
+        assert_eq!(parse_override("title=a = b").unwrap(), table("title = 'a = b'"));
+    }
+
+    #[test]
+    fn parse_override_rejects_bad_settings() {
+        assert!(parse_override("drafts").is_err());
+        assert!(parse_override("=true").is_err());
+        assert!(parse_override("a b=1").is_err());
+    }
+}
@@ -0,0 +1,361 @@
+//! Checking the links between rendered pages.
+//!
+//! Every HTML file in the output directory is scanned for `href` and `src`
+//! attributes. Relative and root-relative references must point at a file in
+//! the output directory, or a directory holding an `index.html`, and their
+//! `#fragment`, if any, at an element of that page with a matching `id` or an
+//! anchor with a matching `name`. Links to other sites are not checked.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+use color_eyre::eyre::{Result, bail};
+use tracing::{event, Level};
+use crate::{percent_decode, Site};
+
+/// A reference from a rendered page which leads nowhere.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrokenLink {
+    /// The page holding the link, relative to the output directory.
+    pub page: String,
+
+    /// The link as it is written in the page.
+    pub link: String,
+
+    /// What is wrong with it.
+    pub reason: String,
+}
+
+impl Site {
+    /// Check every link between the pages in the output directory, returning
+    /// the broken ones ordered by page.
+    pub fn check_links(&self) -> Result<Vec<BrokenLink>> {
+        let mut documents = HashMap::new();
+        for entry in walkdir::WalkDir::new(&self.outdir).min_depth(1).sort_by_file_name() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    event!(Level::ERROR, error = %e, "Unable to read output directory.");
+                    bail!("A fatal error has occurred.");
+                }
+            };
+            if !entry.file_type().is_file() || !is_html(entry.path()) {
+                continue;
+            }
+            let html = match std::fs::read_to_string(entry.path()) {
+                Ok(html) => html,
+                Err(e) => {
+                    let infile = entry.path().as_os_str().to_string_lossy();
+                    event!(Level::ERROR, path = %infile, error = %e, "Unable to read output file.");
+                    bail!("A fatal error has occurred.");
+                }
+            };
+            let page = match entry.path().strip_prefix(&self.outdir) {
+                Ok(page) => page.to_owned(),
+                Err(_) => continue,
+            };
+            documents.insert(page, scan(&html));
+        }
+
+        let mut pages: Vec<&PathBuf> = documents.keys().collect();
+        pages.sort();
+        let mut broken = Vec::new();
+        for page in pages {
+            for link in &documents[page].links {
+                let reason = match resolve(page, link) {
+                    Resolved::External => continue,
+                    Resolved::Outside => "it leads out of the output directory".to_owned(),
+                    Resolved::Internal(target, fragment) => match self.find(&target) {
+                        None => "there is no such file".to_owned(),
+                        Some(target) => match (&fragment, documents.get(&target)) {
+                            (Some(fragment), Some(document)) if !is_top(fragment) && !document.ids.contains(fragment) => {
+                                format!("its page has no `#{fragment}`")
+                            },
+                            _ => continue,
+                        },
+                    },
+                };
+                broken.push(BrokenLink {
+                    page: page.components()
+                        .map(|component| component.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/"),
+                    link: link.clone(),
+                    reason,
+                });
+            }
+        }
+        Ok(broken)
+    }
+
+    /// The file in the output directory a link to `target` is answered with,
+    /// relative to the output directory.
+    fn find(&self, target: &Path) -> Option<PathBuf> {
+        let path = self.outdir.join(target);
+        if path.is_file() {
+            Some(target.to_owned())
+        } else if path.join("index.html").is_file() {
+            Some(target.join("index.html"))
+        } else {
+            None
+        }
+    }
+}
+
+/// The links and link targets of an HTML document.
+#[derive(Debug, Default)]
+struct Document {
+    /// The `href` and `src` attributes, with entities decoded.
+    links: Vec<String>,
+
+    /// The `id` attributes, and `name` attributes of anchors.
+    ids: HashSet<String>,
+}
+
+/// Where a link leads.
+#[derive(Debug, PartialEq, Eq)]
+enum Resolved {
+    /// Another site, or something other than a page like `mailto:`.
+    External,
+
+    /// Above the root of the output directory.
+    Outside,
+
+    /// A path relative to the output directory, and a fragment.
+    Internal(PathBuf, Option<String>),
+}
+
+/// Resolve `link` from the page at `page`, relative to the output directory.
+fn resolve(page: &Path, link: &str) -> Resolved {
+    let link = link.trim();
+    let scheme = link.split_once(':')
+        .is_some_and(|(scheme, _)| !scheme.is_empty() && !scheme.contains(['/', '?', '#']));
+    if link.is_empty() || scheme || link.starts_with("//") {
+        return Resolved::External;
+    }
+
+    let (link, fragment) = match link.split_once('#') {
+        Some((link, fragment)) => (link, Some(percent_decode(fragment))),
+        None => (link, None),
+    };
+    let path = link.split('?').next().unwrap_or_default();
+
+    // The page itself, for links like `#section` or `?page=2`.
+    if path.is_empty() {
+        return Resolved::Internal(page.to_owned(), fragment);
+    }
+
+    let mut segments: Vec<String> = match path.strip_prefix('/') {
+        Some(_) => Vec::new(),
+        None => page.parent()
+            .into_iter()
+            .flat_map(Path::components)
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect(),
+    };
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => (),
+            ".." => {
+                if segments.pop().is_none() {
+                    return Resolved::Outside;
+                }
+            },
+            segment => segments.push(percent_decode(segment)),
+        }
+    }
+    Resolved::Internal(segments.iter().collect(), fragment)
+}
+
+/// Find the links and link targets of an HTML document.
+fn scan(html: &str) -> Document {
+    let mut document = Document::default();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + "-->".len()..]);
+            continue;
+        }
+
+        let end = tag_end(rest);
+        let (name, attributes) = parse_tag(&rest[1..end]);
+        rest = rest.get(end + 1..).unwrap_or_default();
+
+        for (attribute, value) in attributes {
+            match attribute.as_str() {
+                "href" | "src" => document.links.push(decode_entities(value)),
+                "id" => {
+                    document.ids.insert(decode_entities(value));
+                },
+                "name" if name == "a" => {
+                    document.ids.insert(decode_entities(value));
+                },
+                _ => (),
+            }
+        }
+
+        // Scripts and styles are not markup, whatever they contain.
+        if name == "script" || name == "style" {
+            let close = format!("</{name}");
+            rest = match rest.to_ascii_lowercase().find(&close) {
+                Some(index) => &rest[index..],
+                None => "",
+            };
+        }
+    }
+    document
+}
+
+/// The index of the `>` ending the tag at the start of `text`, skipping over
+/// quoted attribute values.
+fn tag_end(text: &str) -> usize {
+    let mut quote = None;
+    for (index, c) in text.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '>') => return index,
+            _ => (),
+        }
+    }
+    text.len()
+}
+
+/// The lowercased name of a tag and its attributes, from what is between its
+/// angle brackets.
+fn parse_tag(tag: &str) -> (String, Vec<(String, &str)>) {
+    let name_end = tag.char_indices()
+        .skip(1)
+        .find(|(_, c)| c.is_whitespace() || *c == '/')
+        .map_or(tag.len(), |(index, _)| index);
+    let name = tag[..name_end].to_ascii_lowercase();
+    if name.starts_with('/') || name.starts_with('!') || name.starts_with('?') {
+        return (name, Vec::new());
+    }
+
+    let mut attributes = Vec::new();
+    let mut rest = &tag[name_end..];
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        if rest.is_empty() {
+            break;
+        }
+        let key_end = rest.find(|c: char| c.is_whitespace() || c == '=' || c == '/').unwrap_or(rest.len());
+        let key = rest[..key_end].to_ascii_lowercase();
+        rest = rest[key_end..].trim_start();
+
+        let value = match rest.strip_prefix('=') {
+            Some(value) => {
+                let value = value.trim_start();
+                match value.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let inner = &value[1..];
+                        let end = inner.find(quote).unwrap_or(inner.len());
+                        rest = inner.get(end + 1..).unwrap_or_default();
+                        &inner[..end]
+                    },
+                    _ => {
+                        let end = value.find(char::is_whitespace).unwrap_or(value.len());
+                        rest = &value[end..];
+                        &value[..end]
+                    },
+                }
+            },
+            None => "",
+        };
+        if !key.is_empty() {
+            attributes.push((key, value));
+        }
+    }
+    (name, attributes)
+}
+
+/// Decode the character references HTML attributes commonly hold.
+fn decode_entities(value: &str) -> String {
+    if !value.contains('&') {
+        return value.to_owned();
+    }
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let reference = rest.find(';').map(|end| (&rest[1..end], end));
+        let character = reference.and_then(|(name, _)| match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => match name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                None => name.strip_prefix('#').and_then(|n| n.parse().ok()).and_then(char::from_u32),
+            },
+        });
+        match (character, reference) {
+            (Some(character), Some((_, end))) => {
+                decoded.push(character);
+                rest = &rest[end + 1..];
+            },
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn is_html(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "html" || extension == "htm")
+}
+
+/// Fragments which lead to the top of any page.
+fn is_top(fragment: &str) -> bool {
+    fragment.is_empty() || fragment.eq_ignore_ascii_case("top")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn internal(path: &str, fragment: Option<&str>) -> Resolved {
+        Resolved::Internal(PathBuf::from(path), fragment.map(str::to_owned))
+    }
+
+    #[test]
+    fn other_sites_are_external() {
+        let page = Path::new("index.html");
+        assert_eq!(resolve(page, "https://example.org/"), Resolved::External);
+        assert_eq!(resolve(page, "//example.org/a.html"), Resolved::External);
+        assert_eq!(resolve(page, "mailto:me@example.org"), Resolved::External);
+        assert_eq!(resolve(page, " "), Resolved::External);
+    }
+
+    #[test]
+    fn relative_links_resolve_from_the_page() {
+        let page = Path::new("posts/2024/a.html");
+        assert_eq!(resolve(page, "b.html"), internal("posts/2024/b.html", None));
+        assert_eq!(resolve(page, "./b.html?x=1#top"), internal("posts/2024/b.html", Some("top")));
+        assert_eq!(resolve(page, "../index.html"), internal("posts/index.html", None));
+        assert_eq!(resolve(page, "../../../up.html"), Resolved::Outside);
+    }
+
+    #[test]
+    fn root_relative_links_resolve_from_the_output_directory() {
+        let page = Path::new("posts/a.html");
+        assert_eq!(resolve(page, "/tags/rust/"), internal("tags/rust", None));
+        assert_eq!(resolve(page, "/my%20page.html#caf%C3%A9"), internal("my page.html", Some("café")));
+        assert_eq!(resolve(page, "/.."), Resolved::Outside);
+    }
+
+    #[test]
+    fn fragments_resolve_to_the_page_itself() {
+        let page = Path::new("posts/a.html");
+        assert_eq!(resolve(page, "#section"), internal("posts/a.html", Some("section")));
+        assert_eq!(resolve(page, "?page=2"), internal("posts/a.html", None));
+    }
+}
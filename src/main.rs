@@ -1,192 +1,182 @@
-use std::{path::PathBuf, io::Write};
+use std::path::PathBuf;
 use color_eyre::eyre::{Result, bail};
-use clap::Parser;
-use fs_extra::dir::CopyOptions;
-use handlebars::no_escape;
-use tracing::{event, Level, span};
+use clap::{Parser, Subcommand};
+use tinytemple::{BuildReport, Context, Site};
+use tracing::{event, Level};
 
 /// Render templates from TOML and Markdown source
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Source directory for template files and content files.
-    #[arg(long, default_value = "./content/")]
+    #[arg(long, global = true, default_value = "./content/")]
     sourcedir: PathBuf,
 
     /// Source directory for files which will be copied verbatim into the output.
-    #[arg(long, default_value = "./static/")]
+    #[arg(long, global = true, default_value = "./static/")]
     staticdir: PathBuf,
 
+    /// Directory of Rhai scripts registered as Handlebars helpers by file name.
+    #[arg(long, global = true, default_value = "./helpers/")]
+    helperdir: PathBuf,
+
+    /// Directory of JSON, YAML, TOML and CSV files exposed to templates as `data`.
+    #[arg(long, global = true, default_value = "./data/")]
+    datadir: PathBuf,
+
     /// Output directory for rendered HTML.
-    #[arg(long, default_value = "./html/")]
+    #[arg(long, global = true, default_value = "./html/")]
     outdir: PathBuf,
 
     /// TOML Configuration file.
-    #[arg(long, default_value = "./tinytemple.toml")]
+    #[arg(long, global = true, default_value = "./tinytemple.toml")]
     config: PathBuf,
+
+    /// Environment whose config overlay, like `tinytemple.production.toml`, is merged over the config file.
+    #[arg(long, global = true, env = "TINYTEMPLE_ENV")]
+    env: Option<String>,
+
+    /// Override a config value, like `--set base_url=https://example.org`. The value is read as TOML if it can be.
+    #[arg(long = "set", global = true, value_name = "KEY.PATH=VALUE", value_parser = parse_set)]
+    set: Vec<Context>,
+
+    /// Also render pages with `draft = true`.
+    #[arg(long, global = true)]
+    drafts: bool,
+
+    /// Also render pages dated in the future.
+    #[arg(long, global = true)]
+    future: bool,
+
+    /// Check the links between pages after every build.
+    #[arg(long, global = true)]
+    check_links: bool,
+
+    /// Cache file used to only re-render what changed since the last build.
+    #[arg(long, global = true, default_value = "./.tinytemple-cache.toml")]
+    cache: PathBuf,
+
+    /// Ignore the cache and render everything from scratch.
+    #[arg(long, global = true)]
+    clean: bool,
+
+    /// Number of pages rendered at once. Defaults to the number of CPU cores.
+    #[arg(long, short, global = true)]
+    jobs: Option<usize>,
 }
 
-type Context = toml::Table;
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Render the site once. This is the default.
+    Build,
 
-fn main() -> Result<()> {
-    use std::time::Instant;
-    let now = Instant::now();
+    /// Rebuild the site whenever its sources, static files or config change.
+    Watch,
+
+    /// Preview the site locally, rebuilding and reloading the browser on changes.
+    Serve {
+        /// Address to listen on.
+        #[arg(long, default_value = tinytemple::serve::DEFAULT_ADDRESS)]
+        address: String,
+    },
 
+    /// Check the links between the pages in the output directory without building.
+    CheckLinks,
+}
+
+fn main() -> Result<()> {
     let subscriber = tracing_subscriber::FmtSubscriber::new();
     tracing::subscriber::set_global_default(subscriber)?;
 
     let args = Args::parse();
 
-    let mut ctx: Context = match std::fs::read_to_string(&args.config) {
-        Ok(raw) => match toml::from_str(&raw) {
-            Ok(cfg) => cfg,
-            Err(e) => {
-                let infile = args.config.as_os_str().to_string_lossy();
-                event!(Level::ERROR, path = %infile, error = %e, "Unable to parse config file.");
-                bail!("A fatal error has occurred.");
-            }
-        },
-        Err(e) => {
-            let infile = args.config.as_os_str().to_string_lossy();
-            event!(Level::ERROR, path = %infile, error = %e, "Unable to read config file.");
-            bail!("A fatal error has occurred.");
-        }
-    };
-
-    match std::fs::read_dir(&args.sourcedir) {
-        Ok(_) => (),
-        Err(e) => {
-            let id = args.sourcedir.as_os_str().to_string_lossy();
-            event!(Level::ERROR, path = %id, error = %e, "Unable to read input directory.");
-            bail!("A fatal error has occurred.");
-        }
-    };
-
-    // First wipe out the old output directory.
-    match std::fs::remove_dir_all(&args.outdir) {
-        Ok(_) => (),
-        Err(e) => {
-            let od = args.outdir.as_os_str().to_string_lossy();
-            event!(Level::ERROR, path = %od, error = %e, "Unable to clear output directory.");
-            bail!("A fatal error has occurred.");
-        }
-    };
-    // Recreate it for use.
-    match std::fs::create_dir_all(&args.outdir) {
-        Ok(_) => (),
-        Err(e) => {
-            let od = args.outdir.as_os_str().to_string_lossy();
-            event!(Level::ERROR, path = %od, error = %e, "Unable to create output directory.");
-            bail!("A fatal error has occurred.");
-        }
-    };
-
-    // Now read all the source files, apply the context, render, and output.
-    let mut engine = handlebars::Handlebars::new();
-    engine.register_escape_fn(no_escape);
-    match engine.register_templates_directory(".hbs", &args.sourcedir) {
-        Ok(_) => (),
-        Err(e) => {
-            let id = args.sourcedir.as_os_str().to_string_lossy();
-            event!(Level::ERROR, path = %id, error = %e, "Unable to parse input templates.");
-            bail!("A fatal error has occurred.");
-        }
+    let site = Site {
+        sourcedir: args.sourcedir,
+        staticdir: args.staticdir,
+        helperdir: args.helperdir,
+        datadir: args.datadir,
+        outdir: args.outdir,
+        config: args.config,
+        env: args.env,
+        overrides: args.set.into_iter().fold(Context::new(), |mut overrides, set| {
+            tinytemple::merge(&mut overrides, set);
+            overrides
+        }),
+        cache: Some(args.cache),
+        jobs: args.jobs.unwrap_or(0),
+        drafts: args.drafts,
+        future: args.future,
+        ..Site::default()
     };
-
-    // Next render every template in sequence.
-    for name in engine.get_templates().keys() {
-        let _span = span!(Level::INFO, "render_template", template = %name).entered();
-        
-        // Render markdown, if there is any.
-        let mut content_file = args.sourcedir.clone();
-        content_file.push(format!("{name}.md"));
-        if content_file.exists() {
-            match std::fs::read_to_string(&content_file) {
-                Ok(raw) => {
-                    let parse_opts = pulldown_cmark::Options::all();
-                    let parser = pulldown_cmark::Parser::new_ext(&raw, parse_opts);
-                    let mut html_output = String::new();
-                    pulldown_cmark::html::push_html(&mut html_output, parser);
-                    ctx.insert("content".to_owned(), toml::Value::String(html_output));
-                },
-                Err(e) => {
-                    let infile = content_file.as_os_str().to_string_lossy();
-                    event!(Level::ERROR, path = %infile, error = %e, "Unable to read content file.");
-                }
-            };
-        }
-        else {
-            ctx.remove("content");
+    if args.clean {
+        if let Some(cache) = &site.cache {
+            match std::fs::remove_file(cache) {
+                Ok(_) => (),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                Err(e) => return Err(e.into()),
+            }
         }
+    }
 
-        // Render the template.
-        let mut outfile = args.outdir.clone();
-        outfile.push(format!("{name}.html"));
-
-        let parentdir = match outfile.parent() {
-            Some(p) => p,
-            None => {
-                let dir = outfile.as_os_str().to_string_lossy();
-                event!(Level::ERROR, path = %dir, "Error manipulating output directory.");
-                continue;
+    match args.command.unwrap_or(Command::Build) {
+        Command::Build => {
+            let report = site.build()?;
+            println!("Finished. ({:.2?})", report.elapsed);
+            if args.check_links {
+                check_links(&site)?;
             }
-        };
-
-        match std::fs::create_dir_all(parentdir) {
-            Ok(_) => (),
-            Err(e) => {
-                let id = args.sourcedir.as_os_str().to_string_lossy();
-                event!(Level::ERROR, path = %id, error = %e, "Unable to create output subdirectory.");
-                continue;
+        },
+        Command::Watch => site.watch(|result| {
+            print_report(&result);
+            if args.check_links && result.is_ok() {
+                let _ = report_links(&site);
             }
-        };
-
-
-
-        match engine.render(name, &ctx) {
-            Ok(rendered) => match std::fs::File::create(&outfile) {
-                Ok(mut fd) => match write!(fd, "{rendered}") {
-                    Ok(_) => (),
-                    Err(e) => {
-                        let outfile = outfile.as_os_str().to_string_lossy();
-                        event!(Level::ERROR, path = %outfile, error = %e, "Error writing to output file.");
-                    }
-                },
-                Err(e) => {
-                    let outfile = content_file.as_os_str().to_string_lossy();
-                    event!(Level::ERROR, path = %outfile, error = %e, "Error creating output file.");
-                }
-            },
-            Err(e) => {
-                let infile = content_file.as_os_str().to_string_lossy();
-                event!(Level::ERROR, path = %infile, error = %e, "Error rendering template.");
+        })?,
+        Command::Serve { address } => site.serve(&address, |result| {
+            print_report(result);
+            if args.check_links && result.is_ok() {
+                let _ = report_links(&site);
             }
-        }
-
-        let _ = _span.exit();
+        })?,
+        Command::CheckLinks => check_links(&site)?,
     }
 
+    Ok(())
+}
 
-    // Last, copy the static directory's contents into the output directory
-    let copy_res = fs_extra::dir::copy(&args.staticdir, &args.outdir, &CopyOptions {
-        overwrite: false,
-        skip_exist: false,
-        copy_inside: false,
-        content_only: true,
-        buffer_size: 64000,
-        depth: 0,
-    });
-
-    match copy_res {
-        Ok(_) => (),
-        Err(e) => {
-            event!(Level::ERROR, error = %e, "Unable to copy static files to output.");
-            bail!("A fatal error has occurred.");
-        }
+/// Check the links of the built site, failing if any are broken.
+fn check_links(site: &Site) -> Result<()> {
+    match report_links(site)? {
+        0 => Ok(()),
+        1 => bail!("Found 1 broken link."),
+        broken => bail!("Found {broken} broken links."),
+    }
+}
+
+/// Log every broken link of the built site, returning how many there are.
+fn report_links(site: &Site) -> Result<usize> {
+    let broken = site.check_links()?;
+    for link in &broken {
+        event!(Level::ERROR, page = %link.page, link = %link.link, reason = %link.reason, "Broken link.");
     }
+    if broken.is_empty() {
+        println!("No broken links.");
+    }
+    Ok(broken.len())
+}
 
-    let elapsed = now.elapsed();
-    println!("Finished. ({:.2?})", elapsed);
+fn parse_set(setting: &str) -> std::result::Result<Context, String> {
+    tinytemple::parse_override(setting).map_err(|e| e.to_string())
+}
 
-    Ok(())
-}
\ No newline at end of file
+/// Report the outcome of a rebuild without exiting on failure.
+fn print_report(result: &Result<BuildReport>) {
+    match result {
+        Ok(report) if report.failed.is_empty() => println!("Finished. ({:.2?})", report.elapsed),
+        Ok(report) => println!("Finished with {} errors. ({:.2?})", report.failed.len(), report.elapsed),
+        Err(e) => event!(Level::ERROR, error = %e, "Build failed."),
+    }
+}
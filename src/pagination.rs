@@ -0,0 +1,114 @@
+//! Pagination of collection listings.
+//!
+//! A template declares that it paginates a collection in its front matter:
+//!
+//! ```toml
+//! +++
+//! paginate = "posts"
+//! per_page = 10
+//! +++
+//! ```
+//!
+//! The first page is rendered to the template's usual output file, and every
+//! following page to `page/<n>/index.html` next to it. Each page receives a
+//! `paginator` table describing its position and holding its slice of entries.
+
+use crate::{url_for, Context};
+
+/// Number of entries per page when a template does not set `per_page`.
+pub const DEFAULT_PER_PAGE: usize = 10;
+
+/// One page of a paginated listing.
+#[derive(Debug, Clone)]
+pub struct Pager {
+    /// Output name of the page, relative to the output directory and without
+    /// the `.html` extension.
+    pub name: String,
+
+    /// The `paginator` table for this page.
+    pub paginator: Context,
+}
+
+/// The output name of page `number` of the listing rendered by `template`.
+pub fn page_name(template: &str, number: usize) -> String {
+    if number <= 1 {
+        return template.to_owned();
+    }
+
+    // `index` pages paginate within their own directory; anything else gets a
+    // directory named after itself.
+    let base = match template.strip_suffix("index") {
+        Some(dir) if dir.is_empty() || dir.ends_with('/') => dir.to_owned(),
+        _ => format!("{template}/"),
+    };
+    format!("{base}page/{number}/index")
+}
+
+/// Split `entries` into pages of `per_page` items for `template`.
+///
+/// An empty listing still produces a single, empty page.
+pub fn paginate(template: &str, entries: &[toml::Value], per_page: usize) -> Vec<Pager> {
+    let per_page = per_page.max(1);
+    let total = entries.len().div_ceil(per_page).max(1);
+    let url = |number: usize| toml::Value::String(url_for(&page_name(template, number)));
+
+    (1..=total)
+        .map(|number| {
+            let start = (number - 1) * per_page;
+            let end = (start + per_page).min(entries.len());
+            let items = entries.get(start..end).unwrap_or_default().to_vec();
+
+            let mut paginator = Context::new();
+            paginator.insert("current".to_owned(), toml::Value::Integer(number as i64));
+            paginator.insert("total".to_owned(), toml::Value::Integer(total as i64));
+            paginator.insert("per_page".to_owned(), toml::Value::Integer(per_page as i64));
+            paginator.insert("total_items".to_owned(), toml::Value::Integer(entries.len() as i64));
+            paginator.insert("url".to_owned(), url(number));
+            paginator.insert("first".to_owned(), url(1));
+            paginator.insert("last".to_owned(), url(total));
+            if number > 1 {
+                paginator.insert("prev".to_owned(), url(number - 1));
+            }
+            if number < total {
+                paginator.insert("next".to_owned(), url(number + 1));
+            }
+            paginator.insert("pages".to_owned(), toml::Value::Array(items));
+
+            Pager {
+                name: page_name(template, number),
+                paginator,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_page_keeps_the_template_name() {
+        assert_eq!(page_name("index", 1), "index");
+        assert_eq!(page_name("archive", 0), "archive");
+    }
+
+    #[test]
+    fn index_pages_paginate_within_their_directory() {
+        assert_eq!(page_name("index", 2), "page/2/index");
+        assert_eq!(page_name("posts/index", 3), "posts/page/3/index");
+    }
+
+    #[test]
+    fn other_pages_paginate_within_a_directory_of_their_own() {
+        assert_eq!(page_name("archive", 2), "archive/page/2/index");
+        assert_eq!(page_name("blog/reindex", 2), "blog/reindex/page/2/index");
+    }
+
+    #[test]
+    fn empty_listings_have_one_page() {
+        let pages = paginate("index", &[], 10);
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].paginator["total"], toml::Value::Integer(1));
+        assert_eq!(pages[0].paginator["pages"], toml::Value::Array(Vec::new()));
+    }
+}
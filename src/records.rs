@@ -0,0 +1,96 @@
+//! One page per record of a data file or collection.
+//!
+//! A template declares the array it renders in its front matter, along with
+//! the permalink every record is written to:
+//!
+//! ```toml
+//! +++
+//! records = "data.products"
+//! permalink = "/products/{slug}/"
+//! +++
+//! ```
+//!
+//! Each `{field}` of the permalink is replaced by a slug of that field of the
+//! record, which may be a dotted path. Permalinks ending in `/` are written to
+//! `index.html` within that directory. Every record is merged into the context
+//! of its own page.
+
+use crate::Context;
+
+/// The value at the dotted `path` within `value`, or `value` itself for an
+/// empty path. Array items are addressed by index.
+pub fn lookup<'a>(value: &'a toml::Value, path: &str) -> Option<&'a toml::Value> {
+    path.split('.')
+        .filter(|key| !key.is_empty())
+        .try_fold(value, |value, key| match value {
+            toml::Value::Array(items) => items.get(key.parse::<usize>().ok()?),
+            toml::Value::Table(table) => table.get(key),
+            _ => None,
+        })
+}
+
+/// The output name of `record`, relative to the output directory and without
+/// the `.html` extension, following `permalink`.
+pub fn output_name(permalink: &str, record: &Context) -> Result<String, String> {
+    let record = toml::Value::Table(record.clone());
+    let mut name = String::new();
+    let mut rest = permalink;
+    while let Some((before, after)) = rest.split_once('{') {
+        let (field, after) = after.split_once('}')
+            .ok_or_else(|| format!("Permalink `{permalink}` has an unclosed `{{`."))?;
+        let value = match lookup(&record, field.trim()) {
+            Some(toml::Value::String(text)) => crate::slugify(text),
+            Some(toml::Value::Table(_) | toml::Value::Array(_)) | None => String::new(),
+            Some(value) => crate::slugify(&value.to_string()),
+        };
+        if value.is_empty() {
+            return Err(format!("A record has no `{field}` for permalink `{permalink}`."));
+        }
+        name.push_str(before);
+        name.push_str(&value);
+        rest = after;
+    }
+    name.push_str(rest);
+
+    let name = name.trim_start_matches('/');
+    Ok(match name.strip_suffix(".html") {
+        Some(name) => name.to_owned(),
+        None if name.is_empty() || name.ends_with('/') => format!("{name}index"),
+        None => name.to_owned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(toml: &str) -> Context {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn permalinks_are_filled_with_slugs() {
+        let product = record("name = 'Blue Widget'\nid = 7\n[maker]\nname = 'ACME Inc.'");
+        assert_eq!(output_name("/products/{name}/", &product).unwrap(), "products/blue-widget/index");
+        assert_eq!(output_name("/{maker.name}/{id}.html", &product).unwrap(), "acme-inc/7");
+        assert_eq!(output_name("{ name }", &product).unwrap(), "blue-widget");
+        assert_eq!(output_name("/", &product).unwrap(), "index");
+    }
+
+    #[test]
+    fn permalinks_need_every_field() {
+        let product = record("name = '!!!'\ntags = ['a']");
+        assert!(output_name("/{id}/", &product).is_err());
+        assert!(output_name("/{name}/", &product).is_err());
+        assert!(output_name("/{tags}/", &product).is_err());
+        assert!(output_name("/{name/", &product).is_err());
+    }
+
+    #[test]
+    fn lookup_follows_paths() {
+        let value = toml::Value::Table(record("[data]\nitems = [{ name = 'a' }]"));
+        assert_eq!(lookup(&value, "data.items.0.name"), Some(&toml::Value::String("a".to_owned())));
+        assert_eq!(lookup(&value, ""), Some(&value));
+        assert_eq!(lookup(&value, "data.items.1"), None);
+    }
+}
@@ -0,0 +1,213 @@
+//! A local preview server with live reload.
+//!
+//! The server hands out the output directory, and injects a small script into
+//! every HTML page which listens for rebuilds over server-sent events and
+//! reloads the page when one finishes.
+
+use std::{
+    io::Write,
+    path::{Component, Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
+use color_eyre::eyre::{Result, bail};
+use tiny_http::{Header, Request, Response, Server};
+use tracing::{event, Level};
+use crate::{escape, percent_decode, BuildReport, RenderFailure, Site};
+
+/// Address the preview server listens on by default.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:8000";
+
+/// Path of the server-sent event stream announcing rebuilds.
+pub const LIVE_RELOAD_PATH: &str = "/__tinytemple/livereload";
+
+/// How often idle event streams are pinged, so that closed connections are
+/// noticed and dropped.
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+const LIVE_RELOAD_SCRIPT: &str = r#"<script>
+(function () {
+    var source = new EventSource("/__tinytemple/livereload");
+    source.onmessage = function () { location.reload(); };
+})();
+</script>
+"#;
+
+/// Browsers waiting to hear about the next rebuild.
+type Clients = Arc<Mutex<Vec<mpsc::Sender<()>>>>;
+
+impl Site {
+    /// Serve the output directory on `address`, rebuilding the site whenever
+    /// its sources change and reloading connected browsers after every build.
+    /// `on_build` is handed the outcome of every build.
+    pub fn serve<F>(&self, address: &str, mut on_build: F) -> Result<()>
+    where
+        F: FnMut(&Result<BuildReport>),
+    {
+        let server = match Server::http(address) {
+            Ok(server) => server,
+            Err(e) => {
+                event!(Level::ERROR, address = %address, error = %e, "Unable to start preview server.");
+                bail!("A fatal error has occurred.");
+            }
+        };
+        event!(Level::INFO, address = %address, "Serving site at http://{address}/");
+
+        let clients = Clients::default();
+        let outdir = self.outdir.clone();
+        let server_clients = clients.clone();
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                handle(request, &outdir, &server_clients);
+            }
+        });
+
+        // Broken pages show their error in the browser.
+        let site = Site { error_pages: true, ..self.clone() };
+        site.watch(|result| {
+            on_build(&result);
+            if result.is_ok() {
+                let mut clients = clients.lock().unwrap_or_else(|e| e.into_inner());
+                clients.retain(|client| client.send(()).is_ok());
+            }
+        })
+    }
+}
+
+fn handle(request: Request, outdir: &Path, clients: &Clients) {
+    let url = request.url().split(['?', '#']).next().unwrap_or("/").to_owned();
+
+    if url == LIVE_RELOAD_PATH {
+        let (tx, rx) = mpsc::channel();
+        clients.lock().unwrap_or_else(|e| e.into_inner()).push(tx);
+        std::thread::spawn(move || stream_events(request, rx));
+        return;
+    }
+
+    let response = match resolve(outdir, &url) {
+        Some(path) => file_response(&path, 200),
+        None => match outdir.join("404.html") {
+            custom if custom.is_file() => file_response(&custom, 404),
+            _ => Response::from_string("Not Found").with_status_code(404),
+        },
+    };
+
+    if let Err(e) = request.respond(response) {
+        event!(Level::DEBUG, url = %url, error = %e, "Unable to send response.");
+    }
+}
+
+/// Hold an event stream open, sending a message after every rebuild.
+fn stream_events(request: Request, rx: mpsc::Receiver<()>) {
+    let mut writer = request.into_writer();
+    let head = "HTTP/1.1 200 OK\r\n\
+        Content-Type: text/event-stream\r\n\
+        Cache-Control: no-cache\r\n\
+        Connection: keep-alive\r\n\r\n";
+    if write!(writer, "{head}").and_then(|_| writer.flush()).is_err() {
+        return;
+    }
+
+    loop {
+        let message = match rx.recv_timeout(KEEPALIVE) {
+            Ok(()) => "data: reload\n\n",
+            Err(mpsc::RecvTimeoutError::Timeout) => ": keepalive\n\n",
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        };
+        if write!(writer, "{message}").and_then(|_| writer.flush()).is_err() {
+            return;
+        }
+    }
+}
+
+/// Map a request path onto a file in the output directory. Directories are
+/// served by their `index.html`.
+fn resolve(outdir: &Path, url: &str) -> Option<PathBuf> {
+    let decoded = percent_decode(url);
+    let relative = Path::new(decoded.trim_start_matches('/'));
+    if relative.components().any(|c| !matches!(c, Component::Normal(_))) {
+        return None;
+    }
+
+    let path = outdir.join(relative);
+    if path.is_dir() {
+        let index = path.join("index.html");
+        return index.is_file().then_some(index);
+    }
+    path.is_file().then_some(path)
+}
+
+fn file_response(path: &Path, status: u16) -> Response<std::io::Cursor<Vec<u8>>> {
+    let mut body = match std::fs::read(path) {
+        Ok(body) => body,
+        Err(e) => {
+            let file = path.as_os_str().to_string_lossy();
+            event!(Level::ERROR, path = %file, error = %e, "Unable to read output file.");
+            return Response::from_string("Internal Server Error").with_status_code(500);
+        }
+    };
+
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    if mime.subtype() == mime_guess::mime::HTML {
+        body = inject_live_reload(&String::from_utf8_lossy(&body)).into_bytes();
+    }
+
+    let content_type = match mime.type_() {
+        mime_guess::mime::TEXT => format!("{mime}; charset=utf-8"),
+        _ => mime.to_string(),
+    };
+    let mut response = Response::from_data(body).with_status_code(status);
+    if let Ok(header) = Header::from_bytes("Content-Type", content_type) {
+        response.add_header(header);
+    }
+    if let Ok(header) = Header::from_bytes("Cache-Control", "no-store") {
+        response.add_header(header);
+    }
+    response
+}
+
+/// A page describing why a page failed to render, shown in its place while
+/// previewing.
+pub fn error_page(failure: &RenderFailure) -> String {
+    let location = match (failure.line, failure.column) {
+        (Some(line), Some(column)) => format!("line {line}, column {column}"),
+        (Some(line), None) => format!("line {line}"),
+        _ => "unknown location".to_owned(),
+    };
+
+    format!(r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Error rendering {template}</title>
+<style>
+    body {{ margin: 0; background: #1e1e1e; color: #eee; font-family: sans-serif; }}
+    .tinytemple-error {{ max-width: 60em; margin: 4em auto; padding: 2em; border-top: 4px solid #e05252; background: #2b2b2b; }}
+    .tinytemple-error h1 {{ margin-top: 0; color: #e05252; font-size: 1.4em; }}
+    .tinytemple-error pre {{ white-space: pre-wrap; padding: 1em; background: #1e1e1e; }}
+</style>
+</head>
+<body>
+<div class="tinytemple-error">
+<h1>Error rendering template</h1>
+<p><strong>{template}</strong> at {location}</p>
+<pre>{error}</pre>
+<p>This page will reload once the problem is fixed.</p>
+</div>
+</body>
+</html>
+"#,
+        template = escape(&failure.template),
+        location = location,
+        error = escape(&failure.error),
+    )
+}
+
+/// Insert the live reload script before the closing `</body>` tag, or at the
+/// end of documents without one.
+pub fn inject_live_reload(html: &str) -> String {
+    match html.to_ascii_lowercase().rfind("</body>") {
+        Some(end) => format!("{}{LIVE_RELOAD_SCRIPT}{}", &html[..end], &html[end..]),
+        None => format!("{html}{LIVE_RELOAD_SCRIPT}"),
+    }
+}
@@ -0,0 +1,366 @@
+//! Shortcodes: templates invoked from within Markdown content.
+//!
+//! A shortcode names a template in the shortcodes directory and passes it
+//! arguments, which may be strings or bare TOML values:
+//!
+//! ```markdown
+//! {{< youtube id="dQw4w9WgXcQ" autoplay=true >}}
+//! ```
+//!
+//! A shortcode may also wrap a body of Markdown, closed by its name:
+//!
+//! ```markdown
+//! {{< note kind="warning" >}}
+//! This is **important**.
+//! {{< /note >}}
+//! ```
+//!
+//! The template `_shortcodes/<name>.hbs` is rendered with the site's variables,
+//! the page's front matter as `page`, its arguments, and its body rendered to
+//! HTML as `body`. Shortcodes inside code are left alone.
+
+use color_eyre::eyre::{Result, bail, eyre};
+use handlebars::Handlebars;
+use crate::{content::Markdown, Context, SHORTCODES_DIR};
+
+/// Stands in for the output of a shortcode while the Markdown around it is
+/// rendered. It has no meaning in Markdown, so it comes through untouched.
+const PLACEHOLDER: char = '\u{fffc}';
+
+/// Renders shortcodes with the templates of a site.
+#[derive(Debug, Clone, Copy)]
+pub struct Shortcodes<'a> {
+    engine: &'a Handlebars<'static>,
+    ctx: &'a Context,
+}
+
+impl<'a> Shortcodes<'a> {
+    /// Render shortcodes with the templates registered in `engine`, and the
+    /// site variables in `ctx`.
+    pub fn new(engine: &'a Handlebars<'static>, ctx: &'a Context) -> Self {
+        Self { engine, ctx }
+    }
+
+    /// Render every shortcode in the Markdown `source` of the page with the
+    /// front matter `page`. Returns the source with a placeholder in place of
+    /// each shortcode, and the HTML of each to [`substitute`] once the rest
+    /// is rendered. Bodies are rendered with `markdown`.
+    pub fn expand(&self, source: &str, markdown: &Markdown, page: &Context) -> Result<(String, Vec<String>)> {
+        let tokens = tokenize(source)?;
+        let mut output = String::with_capacity(source.len());
+        let mut rendered = Vec::new();
+        let mut text_start = 0;
+        let mut index = 0;
+        while index < tokens.len() {
+            let token = &tokens[index];
+            output.push_str(&source[text_start..token.start]);
+            text_start = token.end;
+
+            let (name, args) = match &token.kind {
+                Kind::Open { name, args } => (name, args),
+                Kind::Close { name } => bail!("Shortcode `{name}` is closed but never opened."),
+            };
+            let body = match closing(&tokens, index) {
+                Some(close) => {
+                    let body = markdown.render(&source[token.end..tokens[close].start])?;
+                    text_start = tokens[close].end;
+                    index = close;
+                    Some(body)
+                },
+                None => None,
+            };
+
+            output.push(PLACEHOLDER);
+            output.push_str(&rendered.len().to_string());
+            output.push(PLACEHOLDER);
+            rendered.push(self.render(name, args, body, page)?);
+            index += 1;
+        }
+        output.push_str(&source[text_start..]);
+        Ok((output, rendered))
+    }
+
+    fn render(&self, name: &str, args: &Context, body: Option<String>, page: &Context) -> Result<String> {
+        let template = format!("{SHORTCODES_DIR}/{name}");
+        if !self.engine.has_template(&template) {
+            bail!("Unknown shortcode `{name}`: there is no template `{template}.hbs`.");
+        }
+
+        let mut ctx = self.ctx.clone();
+        ctx.insert("page".to_owned(), toml::Value::Table(page.clone()));
+        ctx.extend(args.clone());
+        if let Some(body) = body {
+            ctx.insert("body".to_owned(), toml::Value::String(body));
+        }
+        match self.engine.render(&template, &ctx) {
+            Ok(html) => Ok(html.trim_end().to_owned()),
+            Err(e) => Err(eyre!("Unable to render shortcode `{name}`: {e}")),
+        }
+    }
+}
+
+/// Put the HTML of each shortcode back in place of its placeholder in the
+/// rendered `html`. Shortcodes which were a paragraph of their own replace the
+/// whole paragraph, so that they can produce block elements.
+pub fn substitute(html: String, rendered: &[String]) -> String {
+    let mut html = html;
+    for (index, output) in rendered.iter().enumerate() {
+        let placeholder = format!("{PLACEHOLDER}{index}{PLACEHOLDER}");
+        let paragraph = format!("<p>{placeholder}</p>\n");
+        html = html.replace(&paragraph, &format!("{output}\n")).replace(&placeholder, output);
+    }
+    html
+}
+
+/// Remove the placeholders of shortcodes from `text`, for uses of the
+/// rendered Markdown which cannot hold their HTML, like heading ids.
+pub fn strip_placeholders(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(PLACEHOLDER) {
+        stripped.push_str(&rest[..start]);
+        let after = &rest[start + PLACEHOLDER.len_utf8()..];
+        let digits = after.len() - after.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        match after[digits..].strip_prefix(PLACEHOLDER) {
+            Some(remaining) if digits > 0 => rest = remaining,
+            _ => {
+                stripped.push(PLACEHOLDER);
+                rest = after;
+            },
+        }
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+/// A shortcode tag, spanning `start..end` of the source.
+#[derive(Debug)]
+struct Token {
+    start: usize,
+    end: usize,
+    kind: Kind,
+}
+
+#[derive(Debug)]
+enum Kind {
+    /// `{{< name key=value >}}`
+    Open { name: String, args: Context },
+
+    /// `{{< /name >}}`
+    Close { name: String },
+}
+
+/// The index of the tag closing the shortcode opened at `open`, if any.
+fn closing(tokens: &[Token], open: usize) -> Option<usize> {
+    let name = match &tokens[open].kind {
+        Kind::Open { name, .. } => name,
+        Kind::Close { .. } => return None,
+    };
+    let mut depth = 0;
+    for (index, token) in tokens.iter().enumerate().skip(open + 1) {
+        match &token.kind {
+            Kind::Open { name: other, .. } if other == name => depth += 1,
+            Kind::Close { name: other } if other == name && depth == 0 => return Some(index),
+            Kind::Close { name: other } if other == name => depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Find the shortcode tags in `source`, skipping over code spans and fenced
+/// code blocks.
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut fence: Option<(u8, usize)> = None;
+    let mut line_start = true;
+    let mut i = 0;
+    while i < bytes.len() {
+        if line_start {
+            let line_end = source[i..].find('\n').map_or(bytes.len(), |n| i + n + 1);
+            let line = &source[i..line_end];
+            let trimmed = line.trim_start_matches(' ');
+            let marker = trimmed.bytes().next().filter(|c| *c == b'`' || *c == b'~');
+            let run = marker.map_or(0, |c| trimmed.bytes().take_while(|b| *b == c).count());
+            if line.len() - trimmed.len() <= 3 && run >= 3 {
+                let marker = marker.unwrap_or_default();
+                match fence {
+                    None => fence = Some((marker, run)),
+                    Some((c, length)) if c == marker && run >= length && trimmed[run..].trim().is_empty() => fence = None,
+                    Some(_) => (),
+                }
+                i = line_end;
+                continue;
+            }
+            if fence.is_some() {
+                i = line_end;
+                continue;
+            }
+            line_start = false;
+        }
+
+        match bytes[i] {
+            b'\n' => {
+                line_start = true;
+                i += 1;
+            },
+            b'`' => {
+                // A code span ends at the next run of as many backticks.
+                let run = bytes[i..].iter().take_while(|b| **b == b'`').count();
+                let mut j = i + run;
+                i = j;
+                while j < bytes.len() {
+                    let closing = bytes[j..].iter().take_while(|b| **b == b'`').count();
+                    if closing == run {
+                        i = j + run;
+                        break;
+                    }
+                    j += closing.max(1);
+                }
+            },
+            b'{' if source[i..].starts_with("{{<") => match source[i..].find(">}}") {
+                Some(length) => {
+                    let end = i + length + ">}}".len();
+                    let kind = parse_tag(&source[i + "{{<".len()..i + length])
+                        .ok_or_else(|| eyre!("Invalid shortcode `{}`.", &source[i..end]))?;
+                    tokens.push(Token { start: i, end, kind });
+                    i = end;
+                },
+                None => i += 1,
+            },
+            _ => i += 1,
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parse the inside of a shortcode tag, like `name key="value"` or `/name`.
+fn parse_tag(inner: &str) -> Option<Kind> {
+    let inner = inner.trim();
+    if let Some(name) = inner.strip_prefix('/') {
+        let name = name.trim();
+        return is_name(name).then(|| Kind::Close { name: name.to_owned() });
+    }
+
+    let (name, mut rest) = inner.split_once(char::is_whitespace).unwrap_or((inner, ""));
+    if !is_name(name) {
+        return None;
+    }
+    let mut args = Context::new();
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        let (key, value) = rest.split_once('=')?;
+        let key = key.trim();
+        if !is_name(key) || key.contains('/') {
+            return None;
+        }
+        let value = value.trim_start();
+        let (value, remaining) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let mut text = String::new();
+                let mut chars = quoted.char_indices();
+                let end = loop {
+                    match chars.next()? {
+                        (_, '\\') => text.push(chars.next()?.1),
+                        (index, '"') => break index + 1,
+                        (_, c) => text.push(c),
+                    }
+                };
+                (toml::Value::String(text), &quoted[end..])
+            },
+            None => {
+                let (bare, remaining) = value.split_once(char::is_whitespace).unwrap_or((value, ""));
+                (bare_value(bare), remaining)
+            },
+        };
+        args.insert(key.to_owned(), value);
+        rest = remaining;
+    }
+    Some(Kind::Open { name: name.to_owned(), args })
+}
+
+/// Unquoted arguments are booleans or numbers, and anything else is a string.
+fn bare_value(text: &str) -> toml::Value {
+    match toml::from_str::<Context>(&format!("value = {text}")) {
+        Ok(mut table) => match table.remove("value") {
+            Some(value @ (toml::Value::Boolean(_) | toml::Value::Integer(_) | toml::Value::Float(_))) => value,
+            _ => toml::Value::String(text.to_owned()),
+        },
+        Err(_) => toml::Value::String(text.to_owned()),
+    }
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The tags found in `source`, as `name` or `/name`.
+    fn tags(source: &str) -> Vec<String> {
+        tokenize(source).unwrap()
+            .into_iter()
+            .map(|token| match token.kind {
+                Kind::Open { name, .. } => name,
+                Kind::Close { name } => format!("/{name}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_tags_and_their_positions() {
+        let source = "Text {{< note >}}body{{< /note >}} more";
+        let tokens = tokenize(source).unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(&source[tokens[0].start..tokens[0].end], "{{< note >}}");
+        assert_eq!(&source[tokens[1].start..tokens[1].end], "{{< /note >}}");
+    }
+
+    #[test]
+    fn parses_arguments() {
+        let tokens = tokenize(r#"{{< figure src="a \"b\".png" width=300 scale=1.5 lazy=true alt=cat >}}"#).unwrap();
+        let args = match &tokens[0].kind {
+            Kind::Open { args, .. } => args,
+            Kind::Close { .. } => panic!("expected an opening tag"),
+        };
+        let expected: Context = toml::from_str(
+            "src = 'a \"b\".png'\nwidth = 300\nscale = 1.5\nlazy = true\nalt = 'cat'"
+        ).unwrap();
+        assert_eq!(args, &expected);
+    }
+
+    #[test]
+    fn skips_code() {
+        let source = "`{{< a >}}` ``x ` {{< b >}}`` {{< c >}}\n\
+            ```\n{{< d >}}\n```\n\
+            ~~~~ text\n{{< e >}}\n~~~\n{{< f >}}\n~~~~\n\
+            {{< g >}}\n    ```\n{{< h >}}\n";
+        assert_eq!(tags(source), ["c", "g", "h"]);
+    }
+
+    #[test]
+    fn strips_placeholders() {
+        assert_eq!(strip_placeholders("Intro \u{fffc}0\u{fffc} and \u{fffc}12\u{fffc}!"), "Intro  and !");
+        assert_eq!(strip_placeholders("\u{fffc}x\u{fffc}"), "\u{fffc}x\u{fffc}");
+        assert_eq!(strip_placeholders("plain"), "plain");
+    }
+
+    #[test]
+    fn unclosed_code_spans_are_text() {
+        assert_eq!(tags("`{{< a >}}"), ["a"]);
+    }
+
+    #[test]
+    fn rejects_invalid_tags() {
+        assert!(tokenize("{{< >}}").is_err());
+        assert!(tokenize("{{< note key >}}").is_err());
+        assert!(tokenize("{{< note key=\"open >}}").is_err());
+        assert!(tags("{{< note").is_empty());
+    }
+}
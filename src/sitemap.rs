@@ -0,0 +1,176 @@
+//! `sitemap.xml` and `robots.txt` generation.
+//!
+//! A sitemap listing every rendered page is written whenever `base_url` is
+//! set. It is tuned by an optional `[sitemap]` configuration table:
+//!
+//! ```toml
+//! [sitemap]
+//! enabled = true               # Set to false to skip the sitemap.
+//! exclude = ["/404.html", "/drafts/**"]
+//! robots = true                # Also write a robots.txt pointing at it.
+//! ```
+//!
+//! Past [`MAX_URLS`] pages the sitemap is split into `sitemap-<n>.xml` files,
+//! and `sitemap.xml` becomes a sitemap index referencing them.
+
+use crate::{escape, permalink, Context, RenderedPage};
+
+/// The most URLs a single sitemap file may list.
+pub const MAX_URLS: usize = 50_000;
+
+/// Sitemap settings read from the `[sitemap]` configuration table.
+#[derive(Debug, Clone)]
+pub struct SitemapConfig {
+    /// URL patterns of pages left out of the sitemap.
+    pub exclude: Vec<glob::Pattern>,
+
+    /// Whether to write a `robots.txt` referencing the sitemap.
+    pub robots: bool,
+
+    /// The most URLs listed by each sitemap file, [`MAX_URLS`] by default.
+    pub max_urls: usize,
+}
+
+impl Default for SitemapConfig {
+    fn default() -> Self {
+        Self { exclude: Vec::new(), robots: false, max_urls: MAX_URLS }
+    }
+}
+
+impl SitemapConfig {
+    /// Read the sitemap settings, or `None` if the sitemap is disabled.
+    /// Invalid exclusion patterns are returned as errors.
+    pub fn from_context(ctx: &Context) -> Option<Result<Self, glob::PatternError>> {
+        let table = match ctx.get("sitemap") {
+            Some(toml::Value::Table(table)) => table.clone(),
+            _ => Context::new(),
+        };
+        if let Some(toml::Value::Boolean(false)) = table.get("enabled") {
+            return None;
+        }
+
+        let mut exclude = Vec::new();
+        if let Some(toml::Value::Array(patterns)) = table.get("exclude") {
+            for pattern in patterns.iter().filter_map(|p| p.as_str()) {
+                match glob::Pattern::new(pattern) {
+                    Ok(pattern) => exclude.push(pattern),
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+        }
+        let robots = matches!(table.get("robots"), Some(toml::Value::Boolean(true)));
+
+        Some(Ok(Self { exclude, robots, ..Default::default() }))
+    }
+
+    /// Whether the page at `url` belongs in the sitemap.
+    pub fn includes(&self, url: &str) -> bool {
+        !self.exclude.iter().any(|pattern| pattern.matches(url))
+    }
+}
+
+/// Render the sitemap for `pages` as a list of `(file name, XML)` pairs. The
+/// first file is always `sitemap.xml`.
+pub fn render(pages: &[RenderedPage], config: &SitemapConfig, ctx: &Context) -> Vec<(String, String)> {
+    let mut pages: Vec<&RenderedPage> = pages.iter().filter(|page| config.includes(&page.url)).collect();
+    pages.sort_by(|a, b| a.url.cmp(&b.url));
+    pages.dedup_by(|a, b| a.url == b.url);
+
+    if pages.len() <= config.max_urls {
+        return vec![("sitemap.xml".to_owned(), urlset(&pages, ctx))];
+    }
+
+    let mut files = vec![("sitemap.xml".to_owned(), String::new())];
+    let mut index = String::new();
+    index.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    index.push_str("<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    for (number, chunk) in pages.chunks(config.max_urls.max(1)).enumerate() {
+        let name = format!("sitemap-{}.xml", number + 1);
+        let loc = permalink(ctx, &name).unwrap_or_else(|| format!("/{name}"));
+        index.push_str(&format!("<sitemap><loc>{}</loc></sitemap>\n", escape(&loc)));
+        files.push((name, urlset(chunk, ctx)));
+    }
+    index.push_str("</sitemapindex>\n");
+    files[0].1 = index;
+    files
+}
+
+fn urlset(pages: &[&RenderedPage], ctx: &Context) -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    for page in pages {
+        let loc = permalink(ctx, &page.url).unwrap_or_else(|| page.url.clone());
+        xml.push_str(&format!("<url><loc>{}</loc>", escape(&loc)));
+        if let Some(lastmod) = &page.lastmod {
+            xml.push_str(&format!("<lastmod>{}</lastmod>", escape(lastmod)));
+        }
+        xml.push_str("</url>\n");
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+/// A `robots.txt` allowing everything and pointing crawlers at the sitemap.
+pub fn robots(ctx: &Context) -> String {
+    let sitemap = permalink(ctx, "sitemap.xml").unwrap_or_else(|| "/sitemap.xml".to_owned());
+    format!("User-agent: *\nAllow: /\n\nSitemap: {sitemap}\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pages(urls: &[&str]) -> Vec<RenderedPage> {
+        urls.iter()
+            .map(|url| RenderedPage { url: (*url).to_owned(), outfile: Default::default(), lastmod: None })
+            .collect()
+    }
+
+    fn config(sitemap: &str) -> SitemapConfig {
+        let ctx: Context = toml::from_str(&format!("[sitemap]\n{sitemap}")).unwrap();
+        SitemapConfig::from_context(&ctx).unwrap().unwrap()
+    }
+
+    #[test]
+    fn reads_the_configuration() {
+        assert!(SitemapConfig::from_context(&Context::new()).is_some());
+        assert!(SitemapConfig::from_context(&toml::from_str("[sitemap]\nenabled = false").unwrap()).is_none());
+        assert!(matches!(SitemapConfig::from_context(&toml::from_str("[sitemap]\nexclude = [\"[\"]").unwrap()), Some(Err(_))));
+        assert!(config("robots = true").robots);
+        assert_eq!(config("").max_urls, MAX_URLS);
+    }
+
+    #[test]
+    fn leaves_out_excluded_pages() {
+        let config = config("exclude = [\"/404.html\", \"/drafts/**\"]");
+        let ctx: Context = toml::from_str("base_url = \"https://example.com\"").unwrap();
+        let files = render(&pages(&["/", "/404.html", "/drafts/", "/drafts/next/", "/posts/a&b/"]), &config, &ctx);
+
+        assert_eq!(files.len(), 1);
+        let (name, xml) = &files[0];
+        assert_eq!(name, "sitemap.xml");
+        assert!(xml.contains("<loc>https://example.com/</loc>"), "{xml}");
+        assert!(xml.contains("<loc>https://example.com/posts/a&amp;b/</loc>"), "{xml}");
+        assert!(!xml.contains("404") && !xml.contains("drafts"), "{xml}");
+    }
+
+    #[test]
+    fn splits_past_the_url_limit() {
+        let config = SitemapConfig { max_urls: 2, ..Default::default() };
+        let files = render(&pages(&["/e/", "/d/", "/c/", "/b/", "/a/", "/a/"]), &config, &Context::new());
+
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["sitemap.xml", "sitemap-1.xml", "sitemap-2.xml", "sitemap-3.xml"]);
+        assert!(files[0].1.contains("<sitemapindex"));
+        assert_eq!(files[0].1.matches("<sitemap>").count(), 3);
+        assert!(files[0].1.contains("<loc>/sitemap-3.xml</loc>"));
+        assert_eq!(files[1].1.matches("<url>").count(), 2);
+        assert!(files[1].1.contains("<loc>/a/</loc>") && files[1].1.contains("<loc>/b/</loc>"));
+        assert_eq!(files[3].1.matches("<url>").count(), 1);
+
+        let files = render(&pages(&["/a/", "/b/"]), &config, &Context::new());
+        assert_eq!(files.len(), 1);
+        assert!(files[0].1.contains("<urlset"));
+    }
+}
@@ -0,0 +1,126 @@
+//! Taxonomies such as tags and categories.
+//!
+//! The taxonomies of a site are listed in the `taxonomies` configuration value
+//! and default to [`DEFAULT_TAXONOMIES`]. Pages are classified by the front
+//! matter value of the same name, which may be a single term or an array.
+//!
+//! For each taxonomy, `_taxonomies/<taxonomy>/list.hbs` is rendered to
+//! `<taxonomy>/index.html` with the list of `terms`, and
+//! `_taxonomies/<taxonomy>/term.hbs` is rendered once per term to
+//! `<taxonomy>/<slug>/index.html`. Either template may be left out.
+
+use std::collections::{BTreeMap, BTreeSet};
+use tracing::{event, Level};
+use crate::{collections, content::Page, slugify, url_for, Context};
+
+/// Taxonomies used when the configuration does not list any.
+pub const DEFAULT_TAXONOMIES: &[&str] = &["tags", "categories"];
+
+/// Directory within the source directory holding the taxonomy templates.
+pub const TAXONOMIES_DIR: &str = "_taxonomies";
+
+/// A single term of a taxonomy and the pages classified under it.
+#[derive(Debug, Clone)]
+pub struct Term<'a> {
+    /// The term as it was first written in front matter.
+    pub name: String,
+
+    /// The term as it appears in URLs.
+    pub slug: String,
+
+    /// Pages classified under the term, newest first.
+    pub pages: Vec<&'a Page>,
+}
+
+impl Term<'_> {
+    /// Output name of the term's page within `taxonomy`.
+    pub fn output_name(&self, taxonomy: &str) -> String {
+        format!("{taxonomy}/{}/index", self.slug)
+    }
+
+    /// The variables describing the term: its `name`, `slug`, `url`, `count`
+    /// and, when `with_pages` is set, the listing entries of its `pages`.
+    pub fn to_context(&self, taxonomy: &str, ctx: &Context, with_pages: bool) -> Context {
+        let mut term = Context::new();
+        term.insert("name".to_owned(), toml::Value::String(self.name.clone()));
+        term.insert("slug".to_owned(), toml::Value::String(self.slug.clone()));
+        term.insert("url".to_owned(), toml::Value::String(url_for(&self.output_name(taxonomy))));
+        term.insert("count".to_owned(), toml::Value::Integer(self.pages.len() as i64));
+        if with_pages {
+            term.insert("pages".to_owned(), toml::Value::Array(self.entries(ctx)));
+        }
+        term
+    }
+
+    /// Listing entries for the term's pages.
+    pub fn entries(&self, ctx: &Context) -> Vec<toml::Value> {
+        self.pages.iter().map(|page| collections::entry(page, ctx)).collect()
+    }
+}
+
+/// The taxonomies configured in `ctx`.
+pub fn configured(ctx: &Context) -> Vec<String> {
+    match ctx.get("taxonomies") {
+        Some(toml::Value::Array(names)) => names.iter()
+            .filter_map(|name| name.as_str())
+            .map(|name| name.to_owned())
+            .collect(),
+        _ => DEFAULT_TAXONOMIES.iter().map(|name| name.to_string()).collect(),
+    }
+}
+
+/// Collect the terms of `taxonomy` used by `pages`, ordered by name.
+pub fn collect<'a>(pages: &'a [Page], taxonomy: &str) -> Vec<Term<'a>> {
+    let mut terms: BTreeMap<String, Term> = BTreeMap::new();
+    let mut merged = BTreeSet::new();
+    for page in pages {
+        let names = match page.front_matter.get(taxonomy) {
+            Some(toml::Value::String(name)) => vec![name.as_str()],
+            Some(toml::Value::Array(names)) => names.iter().filter_map(|n| n.as_str()).collect(),
+            _ => continue,
+        };
+
+        for name in names {
+            let slug = term_slug(name);
+            let term = terms.entry(slug.clone()).or_insert_with(|| Term {
+                name: name.to_owned(),
+                slug,
+                pages: Vec::new(),
+            });
+            if term.name != name && merged.insert(name) {
+                event!(Level::WARN, taxonomy = %taxonomy, term = %name, slug = %term.slug, merged_into = %term.name,
+                    "Term has the same slug as another term, and is merged into it.");
+            }
+            if !term.pages.iter().any(|p| p.name == page.name) {
+                term.pages.push(page);
+            }
+        }
+    }
+
+    let mut terms: Vec<Term> = terms.into_values().collect();
+    for term in &mut terms {
+        term.pages.sort_by(|a, b| collections::compare(a, b));
+    }
+    terms.sort_by_key(|term| term.name.to_lowercase());
+    terms
+}
+
+/// The slug of the term `name`. Terms without any letters or digits, which
+/// slugify to nothing, are spelled out by their bytes instead.
+fn term_slug(name: &str) -> String {
+    match slugify(name) {
+        slug if slug.is_empty() => {
+            let hex: String = name.bytes().map(|byte| format!("{byte:02x}")).collect();
+            format!("term-{hex}")
+        },
+        slug => slug,
+    }
+}
+
+/// The term to pages map of a taxonomy exposed to templates as
+/// `taxonomies.<taxonomy>`.
+pub fn to_context(terms: &[Term], ctx: &Context) -> Context {
+    terms.iter()
+        .map(|term| (term.name.clone(), toml::Value::Array(term.entries(ctx))))
+        .collect()
+}
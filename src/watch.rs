@@ -0,0 +1,122 @@
+//! Rebuilding a site whenever its sources change.
+
+use std::{path::{Path, PathBuf}, sync::mpsc, time::Duration};
+use color_eyre::eyre::{Result, bail};
+use notify::{RecursiveMode, Watcher};
+use tracing::{event, Level};
+use crate::{BuildReport, Site};
+
+/// How long the sources must stay quiet before a rebuild starts, so that a
+/// burst of events from a single save only triggers one build.
+pub const DEBOUNCE: Duration = Duration::from_millis(200);
+
+impl Site {
+    /// Build the site, then rebuild it whenever a file in the source, static,
+    /// helper or data directory, or the configuration file or its overlay,
+    /// changes. The outcome of every build is handed to `on_build`. Build
+    /// errors do not stop watching; this only returns if the file watcher
+    /// itself fails.
+    pub fn watch<F>(&self, mut on_build: F) -> Result<()>
+    where
+        F: FnMut(Result<BuildReport>),
+    {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = match notify::recommended_watcher(tx) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                event!(Level::ERROR, error = %e, "Unable to start file watcher.");
+                bail!("A fatal error has occurred.");
+            }
+        };
+
+        let sourcedir = canonical(&self.sourcedir);
+        let staticdir = canonical(&self.staticdir);
+        let helperdir = canonical(&self.helperdir);
+        let datadir = canonical(&self.datadir);
+        let config = canonical(&self.config);
+        let overlay = self.env.as_deref().map(|env| crate::config_overlay(&config, env));
+
+        // Watch the directory holding the config file rather than the file
+        // itself, since editors often replace files instead of writing them.
+        let config_dir = match config.parent() {
+            Some(dir) if dir.as_os_str().is_empty() => PathBuf::from("."),
+            Some(dir) => dir.to_owned(),
+            None => PathBuf::from("."),
+        };
+        let mut watches = vec![
+            (&sourcedir, RecursiveMode::Recursive),
+            (&staticdir, RecursiveMode::Recursive),
+            (&config_dir, RecursiveMode::NonRecursive),
+        ];
+        // Sites need not have any helpers or data.
+        for dir in [&helperdir, &datadir] {
+            if dir.is_dir() {
+                watches.push((dir, RecursiveMode::Recursive));
+            }
+        }
+        for (path, mode) in watches {
+            if let Err(e) = watcher.watch(path, mode) {
+                let dir = path.as_os_str().to_string_lossy();
+                event!(Level::WARN, path = %dir, error = %e, "Unable to watch path.");
+            }
+        }
+
+        on_build(self.build());
+        event!(Level::INFO, "Watching for changes.");
+
+        // The output directory may sit inside a watched directory, and must
+        // not trigger rebuilds of its own.
+        let outdir = canonical(&self.outdir);
+        let relevant = |path: &Path| {
+            !path.starts_with(&outdir)
+                && (path.starts_with(&sourcedir)
+                    || path.starts_with(&staticdir)
+                    || path.starts_with(&helperdir)
+                    || path.starts_with(&datadir)
+                    || path == config
+                    || overlay.as_deref() == Some(path))
+        };
+
+        loop {
+            let first = match rx.recv() {
+                Ok(first) => first,
+                Err(_) => bail!("File watcher stopped unexpectedly."),
+            };
+
+            // Collect the whole burst of events before deciding to rebuild.
+            let mut changes = vec![first];
+            while let Ok(next) = rx.recv_timeout(DEBOUNCE) {
+                changes.push(next);
+            }
+
+            let mut changed = Vec::new();
+            for change in changes {
+                match change {
+                    Ok(ev) if !ev.kind.is_access() => {
+                        changed.extend(ev.paths.into_iter().filter(|path| relevant(path)));
+                    },
+                    Ok(_) => (),
+                    Err(e) => event!(Level::WARN, error = %e, "File watcher error."),
+                }
+            }
+            if changed.is_empty() {
+                continue;
+            }
+
+            changed.sort();
+            changed.dedup();
+            for path in &changed {
+                let path = path.as_os_str().to_string_lossy();
+                event!(Level::INFO, path = %path, "Change detected.");
+            }
+
+            on_build(self.build());
+        }
+    }
+}
+
+/// Absolute form of `path` for comparing against watcher events, falling back
+/// to the path itself if it does not exist yet.
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_owned())
+}